@@ -0,0 +1,9 @@
+extern crate pkg_config;
+
+fn main() {
+    // Text shaping calls HarfBuzz directly, there is no binding crate among the dependencies
+    pkg_config::Config::new()
+        .atleast_version("1.0")
+        .probe("harfbuzz")
+        .expect("Couldn't find HarfBuzz with pkg-config, install its development package");
+}
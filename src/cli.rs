@@ -0,0 +1,441 @@
+//! Command line interface for exporting an atlas without opening the main window.
+
+use ft;
+use std::fs::{File};
+use std::io::{Read};
+use std::path::{Path, PathBuf};
+use std::rc::{Rc};
+
+use export::{ExportFormat};
+use font::{FontFile};
+use glyphs::{Glyphs};
+use image::{Extrude, ImageFormat, PixelFormat};
+use image_glyph::{ImageGlyph, ImageMetrics};
+use layout::{Align, Layout};
+use render_settings::{GlyphAdjustment, KerningAdjustment, RenderSettings};
+use shaping::{Feature};
+
+pub fn usage() -> String {
+    let formats: Vec<&str> = ExportFormat::all().iter().map(|f| f.id()).collect();
+    let image_formats: Vec<&str> = ImageFormat::all().iter().map(|f| f.id()).collect();
+    let pixel_formats: Vec<&str> = PixelFormat::all().iter().map(|f| f.id()).collect();
+    let extrude_modes: Vec<&str> = Extrude::all().iter().map(|e| e.id()).collect();
+    let aligns: Vec<&str> = Align::all().iter().map(|a| a.id()).collect();
+    format!("Usage: font-atlas [OPTIONS] --output PATH
+       font-atlas --layout PATH --text TEXT [--max-width N] [--align MODE]
+
+Renders the atlas and writes the pages and font descriptor to PATH without opening a window.
+With --layout, lays out the text with the BMFont text descriptor at PATH instead and prints the
+page, source rect and destination rect of each glyph.
+
+Options:
+    --font PATH           Font file, defaults to content/vt323-regular.ttf
+    --fallback PATH       Font used for characters missing from the font, can be repeated
+    --size N              Font size in pixels
+    --extra-size N        Additional size packed into the same pages, can be repeated
+    --text TEXT           Characters to render
+    --text-file PATH      Read the characters to render from a file
+    --glyph N[-M]         Glyph index or range of the font to add, e.g. alternates, can be repeated
+    --image-glyph C=PATH[,X,Y,ADVANCE]
+                          PNG image to use as glyph of codepoint C, scaled to the cap height unless
+                          offsets from the cursor and the baseline and the advance are given, can be
+                          repeated
+    --adjust C=X,Y,ADVANCE
+                          Add to the exported offsets and advance of codepoint C, can be repeated
+    --kern A,B=N          Add N to the kerning of codepoints A and B, can be repeated
+    --padding N           Letter padding
+    --spacing N           Letter spacing
+    --border N            Border width
+    --width N             Page width, defaults to 1024
+    --height N            Page height, defaults to 1024
+    --format FORMAT       One of {}, defaults to the extension of PATH
+    --image FORMAT        Page image format, one of {}, defaults to png
+    --pixels FORMAT       Page channels for formats other than png, one of {}, defaults to rgba8
+    --extrude MODE        Copy glyph edges into the padding, one of {}, defaults to none
+    --mip-levels N        Keep glyphs apart in N mip levels and write mipmaps to dds and ktx2
+    --bpp N               Bits per pixel of pages embedded in source code, 1, 2, 4 or 8
+    --rotate              Turn tall glyphs on their side, not supported by the BMFont formats
+    --hiero               Write a .hiero settings file for libGDX next to .fnt descriptors
+    --shape               Add the glyphs HarfBuzz shapes the text to, listed by the JSON format
+    --feature TAG         OpenType feature whose alternates replace the glyphs of the codepoints,
+                          one of tnum, lnum, onum, smcp, zero or ss01 to ss20, can be repeated
+    --grid                Place glyphs in identical cells in codepoint order and write PATH.grid.json
+    --columns N           Cells in each grid row, defaults to 16
+    --first-codepoint N   Codepoint of the first grid cell, decimal or U+ hex, defaults to 32
+    --cell-width N        Grid cell width, defaults to the widest advance
+    --cell-height N       Grid cell height, defaults to the line height
+    --max-width N         Break laid out lines before they get wider
+    --align MODE          Alignment of laid out lines, one of {}, defaults to left
+    --help                Print this message
+", formats.join(", "), image_formats.join(", "), pixel_formats.join(", "), extrude_modes.join(", "), aligns.join(", "))
+}
+
+pub struct Options {
+    font: String,
+    fallback_fonts: Vec<String>,
+    font_size: Option<isize>,
+    extra_sizes: Vec<isize>,
+    text: Option<String>,
+    glyph_indices: Vec<u32>,
+    image_glyphs: Vec<(u32, String, Option<ImageMetrics>)>,
+    glyph_adjustments: Vec<GlyphAdjustment>,
+    kerning_adjustments: Vec<KerningAdjustment>,
+    letter_padding: Option<isize>,
+    letter_spacing: Option<isize>,
+    border_width: Option<isize>,
+    width: i32,
+    height: i32,
+    format: Option<ExportFormat>,
+    grid: bool,
+    grid_cell_height: Option<u32>,
+    grid_cell_width: Option<u32>,
+    grid_columns: Option<u32>,
+    grid_first_codepoint: Option<u32>,
+    image_format: Option<ImageFormat>,
+    extrude: Option<Extrude>,
+    mip_levels: Option<u32>,
+    pixel_format: Option<PixelFormat>,
+    rotate_glyphs: bool,
+    hiero_settings: bool,
+    shape_text: bool,
+    features: Vec<Feature>,
+    source_bits_per_pixel: Option<u32>,
+    output: PathBuf,
+    layout: Option<PathBuf>,
+    max_width: Option<i32>,
+    align: Option<Align>,
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options {
+            font: String::from("content/vt323-regular.ttf"),
+            fallback_fonts: Vec::new(),
+            font_size: None,
+            extra_sizes: Vec::new(),
+            text: None,
+            glyph_indices: Vec::new(),
+            image_glyphs: Vec::new(),
+            glyph_adjustments: Vec::new(),
+            kerning_adjustments: Vec::new(),
+            letter_padding: None,
+            letter_spacing: None,
+            border_width: None,
+            width: 1024,
+            height: 1024,
+            format: None,
+            grid: false,
+            grid_cell_height: None,
+            grid_cell_width: None,
+            grid_columns: None,
+            grid_first_codepoint: None,
+            image_format: None,
+            extrude: None,
+            mip_levels: None,
+            pixel_format: None,
+            rotate_glyphs: false,
+            hiero_settings: false,
+            shape_text: false,
+            features: Vec::new(),
+            source_bits_per_pixel: None,
+            output: PathBuf::new(),
+            layout: None,
+            max_width: None,
+            align: None,
+        };
+
+        while let Some(arg) = args.next() {
+            if arg == "--help" {
+                return Err(usage());
+            }
+            if arg == "--rotate" {
+                options.rotate_glyphs = true;
+                continue
+            }
+            if arg == "--hiero" {
+                options.hiero_settings = true;
+                continue
+            }
+            if arg == "--shape" {
+                options.shape_text = true;
+                continue
+            }
+            if arg == "--grid" {
+                options.grid = true;
+                continue
+            }
+
+            let value = args.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--font" => options.font = value,
+                "--fallback" => options.fallback_fonts.push(value),
+                "--size" => options.font_size = Some(parse_number(&arg, &value)?),
+                "--extra-size" => options.extra_sizes.push(parse_number(&arg, &value)?),
+                "--text" => options.text = Some(value),
+                "--text-file" => {
+                    let mut text = String::new();
+                    File::open(&value).and_then(|mut f| f.read_to_string(&mut text))
+                        .map_err(|e| format!("Couldn't read {}: {}", value, e))?;
+                    options.text = Some(text);
+                },
+                "--glyph" => options.glyph_indices.extend(parse_glyph_range(&arg, &value)?),
+                "--image-glyph" => options.image_glyphs.push(parse_image_glyph(&arg, &value)?),
+                "--adjust" => options.glyph_adjustments.push(parse_glyph_adjustment(&arg, &value)?),
+                "--kern" => options.kerning_adjustments.push(parse_kerning_adjustment(&arg, &value)?),
+                "--feature" => options.features.push(Feature::from_id(&value).ok_or_else(|| format!("Unknown feature {}", value))?),
+                "--padding" => options.letter_padding = match parse_number(&arg, &value)? {
+                    padding if padding >= 0 => Some(padding),
+                    _ => return Err(format!("Invalid number {} for {}, must not be negative", value, arg)),
+                },
+                "--spacing" => options.letter_spacing = match parse_number(&arg, &value)? {
+                    spacing if spacing >= 0 => Some(spacing),
+                    _ => return Err(format!("Invalid number {} for {}, must not be negative", value, arg)),
+                },
+                "--border" => options.border_width = Some(parse_number(&arg, &value)?),
+                "--width" => options.width = match parse_number(&arg, &value)? {
+                    width if width > 0 => width as i32,
+                    _ => return Err(format!("Invalid number {} for {}, must be at least 1", value, arg)),
+                },
+                "--height" => options.height = match parse_number(&arg, &value)? {
+                    height if height > 0 => height as i32,
+                    _ => return Err(format!("Invalid number {} for {}, must be at least 1", value, arg)),
+                },
+                "--format" => options.format = Some(ExportFormat::from_id(&value).ok_or_else(|| format!("Unknown format {}", value))?),
+                "--image" => options.image_format = Some(ImageFormat::from_id(&value).ok_or_else(|| format!("Unknown image format {}", value))?),
+                "--pixels" => options.pixel_format = Some(PixelFormat::from_id(&value).ok_or_else(|| format!("Unknown pixel format {}", value))?),
+                "--extrude" => options.extrude = Some(Extrude::from_id(&value).ok_or_else(|| format!("Unknown extrude mode {}", value))?),
+                "--mip-levels" => options.mip_levels = match parse_number(&arg, &value)? {
+                    levels @ 0..=16 => Some(levels as u32),
+                    _ => return Err(format!("Invalid number {} for {}, must be at most 16", value, arg)),
+                },
+                "--bpp" => options.source_bits_per_pixel = match parse_number(&arg, &value)? {
+                    bits @ 1 | bits @ 2 | bits @ 4 | bits @ 8 => Some(bits as u32),
+                    _ => return Err(format!("Invalid number {} for {}, must be 1, 2, 4 or 8", value, arg)),
+                },
+                "--columns" => options.grid_columns = match parse_number(&arg, &value)? {
+                    columns if columns > 0 => Some(columns as u32),
+                    _ => return Err(format!("Invalid number {} for {}, must be at least 1", value, arg)),
+                },
+                "--first-codepoint" => options.grid_first_codepoint = Some(parse_codepoint(&arg, &value)?),
+                "--cell-width" => options.grid_cell_width = match parse_number(&arg, &value)? {
+                    size if size >= 0 => Some(size as u32),
+                    _ => return Err(format!("Invalid number {} for {}, must not be negative", value, arg)),
+                },
+                "--cell-height" => options.grid_cell_height = match parse_number(&arg, &value)? {
+                    size if size >= 0 => Some(size as u32),
+                    _ => return Err(format!("Invalid number {} for {}, must not be negative", value, arg)),
+                },
+                "--output" => options.output = PathBuf::from(value),
+                "--layout" => options.layout = Some(PathBuf::from(value)),
+                "--max-width" => options.max_width = Some(parse_number(&arg, &value)? as i32),
+                "--align" => options.align = Some(Align::from_id(&value).ok_or_else(|| format!("Unknown alignment {}", value))?),
+                _ => return Err(format!("Unknown option {}\n\n{}", arg, usage())),
+            }
+        }
+
+        if options.output.as_os_str().is_empty() && options.layout.is_none() {
+            return Err(usage());
+        }
+
+        Ok(options)
+    }
+}
+
+pub fn run(options: &Options) -> Result<(), String> {
+    if let Some(ref path) = options.layout {
+        return print_layout(options, path);
+    }
+
+    let library = Rc::new(ft::Library::init().map_err(|e| format!("Couldn't initialize FreeType: {}", e))?);
+    let face = Rc::new(library.new_face(&options.font, 0).map_err(|e| format!("Couldn't load {}: {}", options.font, e))?);
+    let mut render_settings = RenderSettings::new(&library, &face);
+
+    for path in &options.fallback_fonts {
+        let face = library.new_face(path, 0).map_err(|e| format!("Couldn't load {}: {}", path, e))?;
+        render_settings.fallback_faces.push(Rc::new(face));
+    }
+    if let Some(font_size) = options.font_size {
+        render_settings.font_size = font_size;
+        face.set_char_size(0, font_size * 64, 0, 64).map_err(|e| format!("Invalid size {}: {}", font_size, e))?;
+    }
+    render_settings.extra_sizes = options.extra_sizes.clone();
+    let glyph_count = face.raw().num_glyphs as u32;
+    let (glyph_indices, unknown): (Vec<u32>, Vec<u32>) = options.glyph_indices.iter().partition(|&&g| g > 0 && g < glyph_count);
+    if !unknown.is_empty() {
+        let indices: Vec<String> = unknown.iter().map(|g| g.to_string()).collect();
+        eprintln!("{} has no glyphs with these indices, they were not exported: {}", options.font, indices.join(" "));
+    }
+    render_settings.glyph_indices = glyph_indices;
+    for &adjustment in &options.glyph_adjustments {
+        render_settings.set_glyph_adjustment(adjustment);
+    }
+    for adjustment in &options.kerning_adjustments {
+        render_settings.set_kerning_adjustment(adjustment.first, adjustment.second, adjustment.amount);
+    }
+    for &(codepoint, ref path, metrics) in &options.image_glyphs {
+        let image_glyph = ImageGlyph::load(codepoint as usize, path, metrics).map_err(|e| format!("Couldn't load {}: {}", path, e))?;
+        render_settings.image_glyphs.push(image_glyph);
+    }
+    if let Some(ref text) = options.text {
+        render_settings.text = text.clone();
+    }
+    if let Some(letter_padding) = options.letter_padding {
+        render_settings.letter_padding = letter_padding;
+    }
+    if let Some(letter_spacing) = options.letter_spacing {
+        render_settings.letter_spacing = letter_spacing;
+    }
+    if let Some(border_width) = options.border_width {
+        render_settings.border_width = border_width;
+    }
+    if let Some(image_format) = options.image_format {
+        render_settings.image_format = image_format;
+    }
+    if let Some(extrude) = options.extrude {
+        render_settings.extrude = extrude;
+    }
+    if let Some(mip_levels) = options.mip_levels {
+        render_settings.mip_levels = mip_levels;
+    }
+    if let Some(pixel_format) = options.pixel_format {
+        render_settings.pixel_format = pixel_format;
+    }
+    if let Some(bits) = options.source_bits_per_pixel {
+        render_settings.source_bits_per_pixel = bits;
+    }
+    render_settings.rotate_glyphs = options.rotate_glyphs;
+    render_settings.hiero_settings = options.hiero_settings;
+    render_settings.shape_text = options.shape_text;
+    render_settings.features = options.features.clone();
+    render_settings.grid = options.grid;
+    if let Some(cell_height) = options.grid_cell_height {
+        render_settings.grid_cell_height = cell_height;
+    }
+    if let Some(cell_width) = options.grid_cell_width {
+        render_settings.grid_cell_width = cell_width;
+    }
+    if let Some(columns) = options.grid_columns {
+        render_settings.grid_columns = columns;
+    }
+    if let Some(codepoint) = options.grid_first_codepoint {
+        render_settings.grid_first_codepoint = codepoint;
+    }
+
+    let format = options.format
+        .or_else(|| ExportFormat::from_path(&options.output))
+        .unwrap_or(ExportFormat::BMFont);
+
+    let glyphs = Glyphs::new();
+    let missing = glyphs.write_to_file(&options.output, &render_settings, options.width, options.height, format)
+        .map_err(|e| format!("Couldn't write {}: {}", options.output.display(), e))?;
+
+    if !missing.is_empty() {
+        let codepoints: Vec<String> = missing.iter().map(|c| format!("U+{:04X}", c)).collect();
+        eprintln!("No font contains these characters or they are outside the grid, they were not exported: {}", codepoints.join(" "));
+    }
+
+    Ok(())
+}
+
+fn print_layout(options: &Options, path: &Path) -> Result<(), String> {
+    let font_file = File::open(path).and_then(|mut f| FontFile::parse(&mut f))
+        .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    let text = options.text.as_ref().ok_or_else(|| "Missing --text or --text-file to lay out".to_string())?;
+
+    let mut layout = Layout::new();
+    layout.max_width = options.max_width;
+    layout.align = options.align.unwrap_or(Align::Left);
+
+    for quad in layout.quads(&font_file, text) {
+        let (x, y, width, height) = quad.source;
+        let (left, top, _, _) = quad.destination;
+        println!("quad id={} page={} x={} y={} width={} height={} left={} top={} rotated={}",
+            quad.codepoint, quad.page, x, y, width, height, left, top, quad.rotated as u8);
+    }
+
+    Ok(())
+}
+
+fn parse_number(option: &str, value: &str) -> Result<isize, String> {
+    value.parse().map_err(|_| format!("Invalid number {} for {}", value, option))
+}
+
+/// Parses a codepoint and PNG file like `U+E000=icons/a.png`, optionally followed by the image
+/// metrics like `U+E000=icons/a.png,0,24,28`.
+fn parse_image_glyph(option: &str, value: &str) -> Result<(u32, String, Option<ImageMetrics>), String> {
+    let mut parts = value.splitn(2, '=');
+    let codepoint = parse_codepoint(option, parts.next().unwrap_or(""))?;
+    let image = parts.next().ok_or_else(|| format!("Missing image path in {} for {}", value, option))?;
+
+    let fields: Vec<&str> = image.split(',').collect();
+    match fields.len() {
+        1 => Ok((codepoint, image.to_string(), None)),
+        4 => {
+            let metrics = ImageMetrics {
+                xoffset: parse_number(option, fields[1])? as i32,
+                yoffset: parse_number(option, fields[2])? as i32,
+                xadvance: parse_number(option, fields[3])? as i32,
+            };
+            Ok((codepoint, fields[0].to_string(), Some(metrics)))
+        },
+        _ => Err(format!("Invalid image glyph {} for {}, expected C=PATH or C=PATH,X,Y,ADVANCE", value, option)),
+    }
+}
+
+/// Parses a codepoint and the changes of its offsets and advance like `U+0031=0,0,-2`.
+fn parse_glyph_adjustment(option: &str, value: &str) -> Result<GlyphAdjustment, String> {
+    let mut parts = value.splitn(2, '=');
+    let codepoint = parse_codepoint(option, parts.next().unwrap_or(""))?;
+    let fields: Vec<&str> = parts.next().unwrap_or("").split(',').collect();
+    if fields.len() != 3 {
+        return Err(format!("Invalid adjustment {} for {}, expected C=X,Y,ADVANCE", value, option));
+    }
+
+    Ok(GlyphAdjustment {
+        codepoint: codepoint as usize,
+        xoffset: parse_number(option, fields[0])? as i32,
+        yoffset: parse_number(option, fields[1])? as i32,
+        xadvance: parse_number(option, fields[2])? as i32,
+    })
+}
+
+/// Parses a pair of codepoints and a kerning amount like `U+0054,U+006F=-3`.
+fn parse_kerning_adjustment(option: &str, value: &str) -> Result<KerningAdjustment, String> {
+    let mut parts = value.splitn(2, '=');
+    let pair: Vec<&str> = parts.next().unwrap_or("").split(',').collect();
+    let amount = parts.next().ok_or_else(|| format!("Missing amount in {} for {}", value, option))?;
+    if pair.len() != 2 {
+        return Err(format!("Invalid kerning {} for {}, expected A,B=N", value, option));
+    }
+
+    Ok(KerningAdjustment {
+        first: parse_codepoint(option, pair[0])? as usize,
+        second: parse_codepoint(option, pair[1])? as usize,
+        amount: parse_number(option, amount)? as i32,
+    })
+}
+
+/// Parses a glyph index or an inclusive range of them like `120-135`.
+fn parse_glyph_range(option: &str, value: &str) -> Result<Vec<u32>, String> {
+    let invalid = || format!("Invalid glyph index or range {} for {}", value, option);
+    let mut bounds = value.splitn(2, '-').map(|bound| bound.trim().parse::<u32>());
+    let first = bounds.next().and_then(|bound| bound.ok()).ok_or_else(&invalid)?;
+    let last = match bounds.next() {
+        Some(bound) => bound.map_err(|_| invalid())?,
+        None => first,
+    };
+    if last < first {
+        return Err(invalid());
+    }
+    Ok((first..last + 1).collect())
+}
+
+/// Parses a decimal codepoint or a hexadecimal one prefixed with U+.
+fn parse_codepoint(option: &str, value: &str) -> Result<u32, String> {
+    let parsed = if value.starts_with("U+") || value.starts_with("u+") {
+        u32::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse()
+    };
+    parsed.map_err(|_| format!("Invalid codepoint {} for {}", value, option))
+}
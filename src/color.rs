@@ -0,0 +1,109 @@
+//! Layered color glyphs from the `COLR` (version 0) and `CPAL` tables.
+//! For more info see the [COLR][1] and [CPAL][2] specifications.
+//!
+//! [1]: https://docs.microsoft.com/en-us/typography/opentype/spec/colr
+//! [2]: https://docs.microsoft.com/en-us/typography/opentype/spec/cpal
+//!
+
+use ft;
+use gdk::{RGBA};
+use sfnt::{self, read_u16, read_u32};
+
+/// Palette index of layers drawn in the text color
+const FOREGROUND_COLOR: u16 = 0xffff;
+
+struct BaseGlyph {
+    glyph_index: u16,
+    first_layer: u16,
+    num_layers: u16,
+}
+
+pub struct ColorLayers {
+    base_glyphs: Vec<BaseGlyph>,
+    /// Glyph index and palette entry of each layer
+    layers: Vec<(u16, u16)>,
+    palettes: Vec<Vec<RGBA>>,
+}
+
+impl ColorLayers {
+    /// Reads the layer and palette tables, returns `None` if the face has no COLR v0 glyphs.
+    pub fn from_face(face: &ft::Face) -> Option<ColorLayers> {
+        let colr = sfnt::load_table(face, sfnt::tag(b"COLR"))?;
+        let cpal = sfnt::load_table(face, sfnt::tag(b"CPAL"))?;
+
+        let num_base_glyphs = read_u16(&colr, 2)? as usize;
+        let base_glyphs_offset = read_u32(&colr, 4)? as usize;
+        let layers_offset = read_u32(&colr, 8)? as usize;
+        let num_layers = read_u16(&colr, 12)? as usize;
+        if num_base_glyphs == 0 {
+            return None;
+        }
+
+        let mut base_glyphs = Vec::with_capacity(num_base_glyphs);
+        for i in 0..num_base_glyphs {
+            let offset = base_glyphs_offset + i * 6;
+            base_glyphs.push(BaseGlyph {
+                glyph_index: read_u16(&colr, offset)?,
+                first_layer: read_u16(&colr, offset + 2)?,
+                num_layers: read_u16(&colr, offset + 4)?,
+            });
+        }
+
+        let mut layers = Vec::with_capacity(num_layers);
+        for i in 0..num_layers {
+            let offset = layers_offset + i * 4;
+            layers.push((read_u16(&colr, offset)?, read_u16(&colr, offset + 2)?));
+        }
+
+        let num_palette_entries = read_u16(&cpal, 2)? as usize;
+        let num_palettes = read_u16(&cpal, 4)? as usize;
+        let color_records_offset = read_u32(&cpal, 8)? as usize;
+
+        let mut palettes = Vec::with_capacity(num_palettes);
+        for i in 0..num_palettes {
+            let first_record = read_u16(&cpal, 12 + i * 2)? as usize;
+            let mut palette = Vec::with_capacity(num_palette_entries);
+            for entry in 0..num_palette_entries {
+                let offset = color_records_offset + (first_record + entry) * 4;
+                if offset + 4 > cpal.len() {
+                    return None;
+                }
+                palette.push(RGBA {
+                    red: cpal[offset + 2] as f64 / 255.0,
+                    green: cpal[offset + 1] as f64 / 255.0,
+                    blue: cpal[offset] as f64 / 255.0,
+                    alpha: cpal[offset + 3] as f64 / 255.0,
+                });
+            }
+            palettes.push(palette);
+        }
+
+        Some(ColorLayers {
+            base_glyphs: base_glyphs,
+            layers: layers,
+            palettes: palettes,
+        })
+    }
+
+    /// Layers of a color glyph from bottom to top, each with its glyph index and color. Layers
+    /// drawn in the text color use `foreground`. Returns `None` if the glyph has no layers.
+    pub fn layers(&self, glyph_index: u32, palette: usize, foreground: &RGBA) -> Option<Vec<(u32, RGBA)>> {
+        let base_glyph = self.base_glyphs.binary_search_by_key(&glyph_index, |b| b.glyph_index as u32)
+            .ok()
+            .map(|i| &self.base_glyphs[i])?;
+        let palette = self.palettes.get(palette).or(self.palettes.first())?;
+
+        let first = base_glyph.first_layer as usize;
+        let last = first + base_glyph.num_layers as usize;
+        self.layers.get(first..last).map(|layers| {
+            layers.iter().map(|&(glyph, entry)| {
+                let color = if entry == FOREGROUND_COLOR {
+                    *foreground
+                } else {
+                    palette.get(entry as usize).cloned().unwrap_or(*foreground)
+                };
+                (glyph as u32, color)
+            }).collect()
+        })
+    }
+}
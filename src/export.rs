@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+/// File format of the font descriptor written next to the atlas pages.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+    /// AngelCode BMFont text format
+    BMFont,
+    /// JSON, see the `json` module for the schema
+    Json,
+    /// Godot 3 `BitmapFont` resource
+    Godot,
+    /// TextMeshPro font asset as JSON, see the `text_mesh_pro` module
+    TextMeshPro,
+    /// AngelCode BMFont XML format, read by Phaser and Pixi
+    BMFontXml,
+    /// C header embedding the pages, see the `source` module
+    CSource,
+    /// Rust module embedding the pages, see the `source` module
+    RustSource,
+}
+
+impl ExportFormat {
+    pub fn all() -> &'static [ExportFormat] {
+        static ALL: [ExportFormat; 7] = [
+            ExportFormat::BMFont, ExportFormat::Json, ExportFormat::Godot, ExportFormat::TextMeshPro,
+            ExportFormat::BMFontXml, ExportFormat::CSource, ExportFormat::RustSource,
+        ];
+        &ALL
+    }
+
+    /// Human readable name, used in the save dialog
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ExportFormat::BMFont => "BMFont (*.fnt)",
+            ExportFormat::Json => "JSON (*.json)",
+            ExportFormat::Godot => "Godot BitmapFont (*.tres)",
+            ExportFormat::TextMeshPro => "TextMeshPro (*.tmp.json)",
+            ExportFormat::BMFontXml => "BMFont XML (*.xml)",
+            ExportFormat::CSource => "C header (*.h)",
+            ExportFormat::RustSource => "Rust module (*.rs)",
+        }
+    }
+
+    /// Short identifier, used on the command line
+    pub fn id(&self) -> &'static str {
+        match *self {
+            ExportFormat::BMFont => "fnt",
+            ExportFormat::Json => "json",
+            ExportFormat::Godot => "godot",
+            ExportFormat::TextMeshPro => "tmp",
+            ExportFormat::BMFontXml => "xml",
+            ExportFormat::CSource => "c",
+            ExportFormat::RustSource => "rust",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ExportFormat::BMFont => "fnt",
+            ExportFormat::Json => "json",
+            ExportFormat::Godot => "tres",
+            ExportFormat::TextMeshPro => "tmp.json",
+            ExportFormat::BMFontXml => "xml",
+            ExportFormat::CSource => "h",
+            ExportFormat::RustSource => "rs",
+        }
+    }
+
+    /// Whether the pages are written into the descriptor instead of separate images
+    pub fn embeds_pages(&self) -> bool {
+        match *self {
+            ExportFormat::CSource | ExportFormat::RustSource => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the descriptor can mark glyphs that are stored rotated in the pages
+    pub fn supports_rotation(&self) -> bool {
+        match *self {
+            ExportFormat::Json | ExportFormat::CSource | ExportFormat::RustSource => true,
+            _ => false,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        ExportFormat::all().iter().cloned().find(|f| f.name() == name)
+    }
+
+    pub fn from_id(id: &str) -> Option<ExportFormat> {
+        ExportFormat::all().iter().cloned().find(|f| f.id() == id)
+    }
+
+    /// Format matching the end of the file name, the longest extension wins so `.tmp.json` isn't
+    /// taken for plain JSON.
+    pub fn from_path(path: &Path) -> Option<ExportFormat> {
+        let file_name = path.file_name()?.to_string_lossy().to_string();
+        ExportFormat::all().iter().cloned()
+            .filter(|f| file_name.ends_with(&format!(".{}", f.extension())))
+            .max_by_key(|f| f.extension().len())
+    }
+
+    /// Removes the extension of this format from the path, so new extensions aren't appended to it.
+    pub fn strip_extension(&self, path: &Path) -> PathBuf {
+        let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        match file_name.rfind(&format!(".{}", self.extension())) {
+            Some(index) if index > 0 && index + self.extension().len() + 1 == file_name.len() => path.with_file_name(&file_name[..index]),
+            _ => path.to_path_buf(),
+        }
+    }
+}
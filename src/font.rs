@@ -1,11 +1,13 @@
-//! Module for writing a bitmap font.
+//! Module for reading and writing a bitmap font.
 //! For more info see the [file format specification][1] and [text rendering page][2].
 //!
 //! [1]: http://www.angelcode.com/products/bmfont/doc/file_format.html
 //! [2]: http://www.angelcode.com/products/bmfont/doc/render_text.html
 //!
 
+use std::cmp;
 use std::io;
+use std::str::{FromStr};
 
 pub struct InfoTag {
     pub face: String,
@@ -150,6 +152,9 @@ pub struct CharTag {
     /// Identifies the bitmap font page containing this character
     pub page: u32,
     pub chnl: u8,
+    /// The glyph is stored turned 90 degrees clockwise, covering `height` pixels to the right
+    /// and `width` pixels down from `x` and `y`. The BMFont formats can't express this.
+    pub rotated: bool,
 }
 
 impl CharTag {
@@ -179,20 +184,175 @@ impl CharTag {
     }
 }
 
+pub struct KerningsTag {
+    pub count: u32,
+}
+
+impl KerningsTag {
+    fn write_to(&self, out: &mut PrintWriter) -> io::Result<()> {
+        out.write_str("kernings count=")?;
+        out.write_u32(self.count)?;
+        out.write_str("\n")?;
+        Ok(())
+    }
+}
+
+pub struct KerningTag {
+    /// First character id of the pair
+    pub first: usize,
+    /// Second character id of the pair
+    pub second: usize,
+    /// How much the x position should be adjusted when drawing the second character immediately following the first
+    pub amount: i32,
+}
+
+impl KerningTag {
+    fn write_to(&self, out: &mut PrintWriter) -> io::Result<()> {
+        out.write_str("kerning first=")?;
+        out.write_usize(self.first)?;
+        out.write_str(" second=")?;
+        out.write_usize(self.second)?;
+        out.write_str(" amount=")?;
+        out.write_i32(self.amount)?;
+        out.write_str("\n")?;
+        Ok(())
+    }
+}
+
+/// Glyph listed by its index in the face, not part of the BMFont formats.
+pub struct GlyphTag {
+    /// Placement of the glyph, with the glyph index as id
+    pub char: CharTag,
+    /// Codepoints the face maps to the glyph, empty for unencoded glyphs
+    pub codepoints: Vec<usize>,
+}
+
+/// Glyphs text shaping maps a run of characters to, not part of the BMFont formats.
+pub struct SubstitutionTag {
+    /// Character ids of the run
+    pub codepoints: Vec<usize>,
+    /// Characters right before and after the run that shaping saw, `None` at the start or end
+    /// of a line
+    pub before: Option<usize>,
+    pub after: Option<usize>,
+    /// Ids of the entries in `FontFile::glyphs` to draw instead
+    pub glyphs: Vec<u32>,
+    /// Offset of each glyph from the cursor, added to the offsets of its entry
+    pub offsets: Vec<(i32, i32)>,
+    /// Advance of the cursor after each glyph, replacing the advance of its entry
+    pub advances: Vec<(i32, i32)>,
+}
+
 pub struct FontFile {
     pub info: InfoTag,
     pub common: CommonTag,
-    pub page: PageTag,
+    pub pages: Vec<PageTag>,
     pub chars: Vec<CharTag>,
+    pub kernings: Vec<KerningTag>,
+    /// Glyphs by their index in the face, only for shaped text and glyphs requested by index
+    pub glyphs: Vec<GlyphTag>,
+    pub substitutions: Vec<SubstitutionTag>,
 }
 
 impl FontFile {
+    /// Reads the text format. Unknown tags and attributes are ignored and missing attributes
+    /// are 0 or empty, but the info and common tags are required.
+    pub fn parse(read: &mut io::Read) -> io::Result<FontFile> {
+        let mut text = String::new();
+        read.read_to_string(&mut text)?;
+
+        let mut info = None;
+        let mut common = None;
+        let mut pages = Vec::new();
+        let mut chars = Vec::new();
+        let mut kernings = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let name_end = line.find(char::is_whitespace).unwrap_or(line.len());
+            let tag = Tag {
+                line: index + 1,
+                attributes: Tag::attributes(&line[name_end..]),
+            };
+
+            match &line[..name_end] {
+                "info" => info = Some(InfoTag {
+                    face: tag.string("face"),
+                    size: tag.number("size")?,
+                    bold: tag.bool("bold")?,
+                    italic: tag.bool("italic")?,
+                    charset: tag.string("charset"),
+                    unicode: tag.bool("unicode")?,
+                    stretch_h: tag.number("stretchH")?,
+                    smooth: tag.bool("smooth")?,
+                    aa: tag.bool("aa")?,
+                    padding: {
+                        let padding = tag.numbers("padding", 4)?;
+                        [padding[0], padding[1], padding[2], padding[3]]
+                    },
+                    spacing: {
+                        let spacing = tag.numbers("spacing", 2)?;
+                        [spacing[0], spacing[1]]
+                    },
+                    outline: tag.number("outline")?,
+                }),
+                "common" => common = Some(CommonTag {
+                    line_height: tag.number("lineHeight")?,
+                    base: tag.number("base")?,
+                    scale_w: tag.number("scaleW")?,
+                    scale_h: tag.number("scaleH")?,
+                    pages: tag.number("pages")?,
+                    packed: tag.bool("packed")?,
+                    alpha_channel: tag.number("alphaChnl")?,
+                    red_channel: tag.number("redChnl")?,
+                    green_channel: tag.number("greenChnl")?,
+                    blue_channel: tag.number("blueChnl")?,
+                }),
+                "page" => pages.push(PageTag {
+                    id: tag.number("id")?,
+                    file: tag.string("file"),
+                }),
+                "char" => chars.push(CharTag {
+                    id: tag.number("id")?,
+                    x: tag.number("x")?,
+                    y: tag.number("y")?,
+                    width: tag.number("width")?,
+                    height: tag.number("height")?,
+                    xoffset: tag.number("xoffset")?,
+                    yoffset: tag.number("yoffset")?,
+                    xadvance: tag.number("xadvance")?,
+                    page: tag.number("page")?,
+                    chnl: tag.number("chnl")?,
+                    rotated: false,
+                }),
+                "kerning" => kernings.push(KerningTag {
+                    first: tag.number("first")?,
+                    second: tag.number("second")?,
+                    amount: tag.number("amount")?,
+                }),
+                _ => {},
+            }
+        }
+
+        Ok(FontFile {
+            info: info.ok_or_else(|| invalid_data("Missing info tag".to_string()))?,
+            common: common.ok_or_else(|| invalid_data("Missing common tag".to_string()))?,
+            pages: pages,
+            chars: chars,
+            kernings: kernings,
+            glyphs: Vec::new(),
+            substitutions: Vec::new(),
+        })
+    }
+
     pub fn write(&self, write: &mut io::Write) -> io::Result<()> {
         let mut out = PrintWriter::new(write);
 
         self.info.write_to(&mut out)?;
         self.common.write_to(&mut out)?;
-        self.page.write_to(&mut out)?;
+        for page in &self.pages {
+            page.write_to(&mut out)?;
+        }
         
         CharsTag {
             count: self.chars.len() as u32,
@@ -202,8 +362,135 @@ impl FontFile {
             char.write_to(&mut out)?;
         }
 
+        if !self.kernings.is_empty() {
+            KerningsTag {
+                count: self.kernings.len() as u32,
+            }.write_to(&mut out)?;
+
+            for kerning in &self.kernings {
+                kerning.write_to(&mut out)?;
+            }
+        }
+
         Ok(())
     }
+
+    /// Writes the XML variant of the format, with the same tags and attributes as the text one.
+    pub fn write_xml(&self, write: &mut io::Write) -> io::Result<()> {
+        let info = &self.info;
+        let common = &self.common;
+
+        write!(write, "<?xml version=\"1.0\"?>\n<font>\n")?;
+        write!(write, "  <info face=\"{}\" size=\"{}\" bold=\"{}\" italic=\"{}\" charset=\"{}\" unicode=\"{}\" stretchH=\"{}\" smooth=\"{}\" aa=\"{}\" padding=\"{},{},{},{}\" spacing=\"{},{}\" outline=\"{}\"/>\n",
+            xml_escape(&info.face), info.size, info.bold as u8, info.italic as u8, xml_escape(&info.charset), info.unicode as u8, info.stretch_h, info.smooth as u8, info.aa as u8,
+            info.padding[0], info.padding[1], info.padding[2], info.padding[3], info.spacing[0], info.spacing[1], info.outline)?;
+        write!(write, "  <common lineHeight=\"{}\" base=\"{}\" scaleW=\"{}\" scaleH=\"{}\" pages=\"{}\" packed=\"{}\" alphaChnl=\"{}\" redChnl=\"{}\" greenChnl=\"{}\" blueChnl=\"{}\"/>\n",
+            common.line_height, common.base, common.scale_w, common.scale_h, common.pages, common.packed as u8,
+            common.alpha_channel, common.red_channel, common.green_channel, common.blue_channel)?;
+
+        write!(write, "  <pages>\n")?;
+        for page in &self.pages {
+            write!(write, "    <page id=\"{}\" file=\"{}\"/>\n", page.id, xml_escape(&page.file))?;
+        }
+        write!(write, "  </pages>\n")?;
+
+        write!(write, "  <chars count=\"{}\">\n", self.chars.len())?;
+        for char in &self.chars {
+            write!(write, "    <char id=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" xoffset=\"{}\" yoffset=\"{}\" xadvance=\"{}\" page=\"{}\" chnl=\"{}\"/>\n",
+                char.id, char.x, char.y, char.width, char.height, char.xoffset, char.yoffset, char.xadvance, char.page, char.chnl)?;
+        }
+        write!(write, "  </chars>\n")?;
+
+        if !self.kernings.is_empty() {
+            write!(write, "  <kernings count=\"{}\">\n", self.kernings.len())?;
+            for kerning in &self.kernings {
+                write!(write, "    <kerning first=\"{}\" second=\"{}\" amount=\"{}\"/>\n", kerning.first, kerning.second, kerning.amount)?;
+            }
+            write!(write, "  </kernings>\n")?;
+        }
+
+        write!(write, "</font>\n")
+    }
+}
+
+/// Attributes of a tag of the text format, with the line it was read from for error messages.
+struct Tag {
+    line: usize,
+    attributes: Vec<(String, String)>,
+}
+
+impl Tag {
+    /// Splits the attributes into keys and values. Values may be quoted, unquoted values
+    /// continue up to the next key, as `write` doesn't quote face and file names.
+    fn attributes(text: &str) -> Vec<(String, String)> {
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        let mut rest = text.trim_start();
+
+        while !rest.is_empty() {
+            let token_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let token = &rest[..token_end];
+            match token.find('=') {
+                Some(equals) if token[equals + 1..].starts_with('"') => {
+                    let value_start = equals + 2;
+                    let value_end = rest[value_start..].find('"').map(|end| value_start + end).unwrap_or(rest.len());
+                    attributes.push((token[..equals].to_string(), rest[value_start..value_end].to_string()));
+                    rest = rest[cmp::min(value_end + 1, rest.len())..].trim_start();
+                    continue
+                },
+                Some(equals) => attributes.push((token[..equals].to_string(), token[equals + 1..].to_string())),
+                None => if let Some(last) = attributes.last_mut() {
+                    last.1.push(' ');
+                    last.1.push_str(token);
+                },
+            }
+            rest = rest[token_end..].trim_start();
+        }
+
+        attributes
+    }
+
+    fn value(&self, key: &str) -> Option<&str> {
+        self.attributes.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v.as_str())
+    }
+
+    fn string(&self, key: &str) -> String {
+        self.value(key).unwrap_or("").to_string()
+    }
+
+    fn number<T: FromStr + Default>(&self, key: &str) -> io::Result<T> {
+        match self.value(key) {
+            Some(value) => value.parse().map_err(|_| self.invalid(key, value)),
+            None => Ok(T::default()),
+        }
+    }
+
+    fn bool(&self, key: &str) -> io::Result<bool> {
+        self.number::<u32>(key).map(|value| value != 0)
+    }
+
+    /// Comma separated numbers, missing ones are 0
+    fn numbers(&self, key: &str, count: usize) -> io::Result<Vec<u32>> {
+        let mut numbers = Vec::with_capacity(count);
+        if let Some(value) = self.value(key) {
+            for number in value.split(',') {
+                numbers.push(number.trim().parse().map_err(|_| self.invalid(key, value))?);
+            }
+        }
+        numbers.resize(count, 0);
+        Ok(numbers)
+    }
+
+    fn invalid(&self, key: &str, value: &str) -> io::Error {
+        invalid_data(format!("Invalid value {} for {} in line {}", value, key, self.line))
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
 }
 
 struct PrintWriter<'a> {
@@ -246,3 +533,71 @@ impl<'a> PrintWriter<'a> {
         self.writer.write_all(value.to_string().as_bytes())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT: &str = "info face=DejaVu Sans size=16 bold=0 italic=1 charset= unicode=1 stretchH=100 smooth=1 aa=1 padding=1,2,3,4 spacing=1,1 outline=0
+common lineHeight=19 base=15 scaleW=256 scaleH=128 pages=1 packed=0 alphaChnl=0 redChnl=4 greenChnl=4 blueChnl=4
+page id=0 file=dejavu sans_0.png
+chars count=2
+char id=65 x=0 y=0 width=11 height=12 xoffset=-1 yoffset=3 xadvance=10 page=0 chnl=15
+char id=86 x=12 y=0 width=11 height=12 xoffset=0 yoffset=3 xadvance=10 page=0 chnl=15
+kernings count=1
+kerning first=65 second=86 amount=-2
+";
+
+    fn parse(text: &str) -> io::Result<FontFile> {
+        FontFile::parse(&mut text.as_bytes())
+    }
+
+    #[test]
+    fn parses_what_it_writes() {
+        let font_file = parse(FONT).unwrap();
+        assert_eq!(font_file.info.face, "DejaVu Sans");
+        assert_eq!(font_file.info.padding, [1, 2, 3, 4]);
+        assert!(font_file.info.italic && !font_file.info.bold);
+        assert_eq!(font_file.common.base, 15);
+        assert_eq!(font_file.pages[0].file, "dejavu sans_0.png");
+        assert_eq!(font_file.chars.len(), 2);
+        assert_eq!((font_file.chars[0].id, font_file.chars[0].xoffset, font_file.chars[1].x), (65, -1, 12));
+        assert_eq!((font_file.kernings[0].first, font_file.kernings[0].second, font_file.kernings[0].amount), (65, 86, -2));
+
+        let mut written = Vec::new();
+        font_file.write(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), FONT);
+    }
+
+    #[test]
+    fn splits_quoted_and_unquoted_attributes() {
+        let attributes = Tag::attributes(r#" face="Open Sans" size=12 file=my font.png charset="" id=3"#);
+        let expected = [("face", "Open Sans"), ("size", "12"), ("file", "my font.png"), ("charset", ""), ("id", "3")];
+        assert_eq!(attributes.len(), expected.len());
+        for (&(ref key, ref value), &(expected_key, expected_value)) in attributes.iter().zip(&expected) {
+            assert_eq!((key.as_str(), value.as_str()), (expected_key, expected_value));
+        }
+    }
+
+    #[test]
+    fn defaults_missing_attributes() {
+        let font_file = parse("info face=\"A\"\ncommon\nunknown tag=1\nchar id=32 xadvance=4\n").unwrap();
+        assert_eq!(font_file.info.face, "A");
+        assert_eq!(font_file.info.spacing, [0, 0]);
+        assert_eq!(font_file.common.line_height, 0);
+        assert_eq!((font_file.chars[0].id, font_file.chars[0].width, font_file.chars[0].xadvance), (32, 0, 4));
+    }
+
+    #[test]
+    fn requires_info_and_common() {
+        assert_eq!(parse("common lineHeight=1\n").err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(parse("info face=A\n").err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reports_invalid_numbers_with_line() {
+        let error = parse("info face=A\ncommon\nchar id=x\n").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("line 3"));
+    }
+}
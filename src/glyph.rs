@@ -1,31 +1,71 @@
-use cairo::{Format, ImageSurface};
+use cairo::{Context, Format, ImageSurface, Operator};
 use ft;
 use ft::freetype_sys as fts;
 use gdk::{RGBA};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap};
 use std::cmp;
 use std::mem;
 use std::os::raw::c_long;
 use std::ptr;
 use std::rc::{Rc};
 
+use color::{ColorLayers};
+use opentype::{self, PairKerning};
+use sfnt::{self, read_u16};
+use shaping::{Feature};
+
 pub struct RenderedGlyph {
     /// The rendered glyph
     pub surface: ImageSurface,
-    /// Unicode codepoint
-    pub codepoint: usize,
+    /// Unicode codepoint, `None` for glyphs rendered by glyph index
+    pub codepoint: Option<usize>,
+    /// Index into the fallback chain of the face that rendered the glyph, 0 is the primary face
+    pub face: usize,
+    /// Index of the glyph in the face, not meaningful for images
+    pub glyph_index: u32,
+    /// An image packed as the glyph of a codepoint instead of a glyph of a face
+    pub image: bool,
+    /// Codepoints the cmap of the face maps to a glyph rendered by index, empty for unencoded
+    /// glyphs like alternates or ligatures and for glyphs rendered by codepoint
+    pub codepoints: Vec<usize>,
     // Distance to move the text cursor forward to render the next glyph, in pixels
     pub advance: (i32, i32),
     // Offset of top-left corner from baseline, in pixels
     pub offset: (i32, i32),
 }
 
+/// Codepoints the cmap of a face maps to each glyph index
+type ReverseCmap = HashMap<u32, Vec<usize>>;
+
+extern "C" {
+    fn FT_Outline_Embolden(outline: *mut fts::FT_Outline, strength: fts::FT_Pos) -> fts::FT_Error;
+}
+
 pub struct Renderer {
     library: Rc<ft::Library>,
     face: Rc<ft::Face<'static>>,
+    fallback_faces: Vec<Rc<ft::Face<'static>>>,
+    /// COLR layers of each face in the fallback chain
+    color_layers: Vec<Option<ColorLayers>>,
+    /// GPOS kerning of each face in the fallback chain
+    pair_kernings: Vec<Option<PairKerning>>,
+    /// Codepoints of each glyph for each face in the fallback chain, walking a cmap takes a
+    /// while, so they are only built when a glyph rendered by index asks for them
+    reverse_cmaps: RefCell<Vec<Option<ReverseCmap>>>,
+    color_glyphs: bool,
+    color_palette: usize,
+    features: Vec<Feature>,
+    /// Alternates the features substitute for glyphs, for each face in the fallback chain
+    alternates: Vec<HashMap<u32, u32>>,
+    size: Cell<isize>,
     color: RGBA,
     outline_stroker: Option<Stroker>,
     outline_color: RGBA,
     outline_width: isize,
+    bold_strength: f64,
+    oblique_angle: f64,
+    stretch: u32,
 }
 
 impl Renderer {
@@ -34,13 +74,145 @@ impl Renderer {
         Renderer {
             library: library.clone(),
             face: face.clone(),
+            fallback_faces: Vec::new(),
+            color_layers: vec![ColorLayers::from_face(face)],
+            pair_kernings: vec![PairKerning::from_face(face)],
+            reverse_cmaps: RefCell::new(vec![None]),
+            color_glyphs: false,
+            color_palette: 0,
+            features: Vec::new(),
+            alternates: vec![HashMap::new()],
+            size: Cell::new(0),
             color: RGBA::black(),
             outline_stroker: None,
             outline_color: RGBA::black(),
             outline_width: 0,
+            bold_strength: 0.0,
+            oblique_angle: 0.0,
+            stretch: 100,
+        }
+    }
+
+    /// Sets the size of all faces glyphs get rendered at, in pixels. Bitmap fonts select the
+    /// closest strike, their glyphs get scaled when rendering.
+    pub fn set_char_size(&self, size: isize) -> ft::FtResult<()> {
+        self.size.set(size);
+        for face in self.faces() {
+            if face.is_scalable() {
+                face.set_char_size(0, size * 64, 0, 64)?;
+            } else {
+                Renderer::select_strike(face, size)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Faces tried in order for codepoints the primary face doesn't contain.
+    pub fn set_fallback_faces(&mut self, faces: &[Rc<ft::Face<'static>>]) {
+        self.fallback_faces = faces.to_vec();
+        self.color_layers.truncate(1);
+        self.color_layers.extend(faces.iter().map(|face| ColorLayers::from_face(face)));
+        self.pair_kernings.truncate(1);
+        self.pair_kernings.extend(faces.iter().map(|face| PairKerning::from_face(face)));
+        *self.reverse_cmaps.borrow_mut() = vec![None; faces.len() + 1];
+        let alternates: Vec<HashMap<u32, u32>> = faces.iter().map(|face| self.read_alternates(face)).collect();
+        self.alternates.truncate(1);
+        self.alternates.extend(alternates);
+    }
+
+    /// Codepoints the cmap of the face at `face_index` in the fallback chain maps to the glyph.
+    fn codepoints(&self, face_index: usize, glyph_index: u32) -> Vec<usize> {
+        let face = match self.faces().get(face_index) {
+            Some(face) => Rc::clone(face),
+            None => return Vec::new(),
+        };
+        let mut reverse_cmaps = self.reverse_cmaps.borrow_mut();
+        reverse_cmaps[face_index].get_or_insert_with(|| Renderer::reverse_cmap(&face))
+            .get(&glyph_index)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn reverse_cmap(face: &ft::Face) -> ReverseCmap {
+        let raw = face.raw() as *const fts::FT_FaceRec as fts::FT_Face;
+        let mut cmap: ReverseCmap = HashMap::new();
+        unsafe {
+            let mut glyph_index = 0;
+            let mut codepoint = fts::FT_Get_First_Char(raw, &mut glyph_index);
+            while glyph_index != 0 {
+                cmap.entry(glyph_index).or_insert_with(Vec::new).push(codepoint as usize);
+                codepoint = fts::FT_Get_Next_Char(raw, codepoint, &mut glyph_index);
+            }
+        }
+        cmap
+    }
+
+    /// Height of capital letters above the baseline in pixels, taken from the OS/2 table or the
+    /// outline of 'H' of the primary face, the ascender if neither is available.
+    pub fn cap_height(&self) -> i32 {
+        let face = &self.face;
+        if face.is_scalable() && face.em_size() > 0 {
+            let os2 = sfnt::load_table(face, sfnt::tag(b"OS/2"));
+            let cap_height = os2.as_ref()
+                .filter(|os2| read_u16(os2, 0).unwrap_or(0) >= 2)
+                .and_then(|os2| read_u16(os2, 88))
+                .map(|units| units as i16);
+            if let Some(units) = cap_height.filter(|&units| units > 0) {
+                return (units as f64 * self.size.get() as f64 / face.em_size() as f64).round() as i32;
+            }
+
+            let glyph_index = face.get_char_index('H' as usize);
+            if glyph_index != 0 && face.load_glyph(glyph_index, ft::face::LoadFlag::DEFAULT).is_ok() {
+                return (face.glyph().metrics().horiBearingY >> 6) as i32;
+            }
+        }
+
+        self.size_metrics(0).map(|metrics| metrics.ascender as i32 / 64).unwrap_or(0)
+    }
+
+    /// Size metrics of the face at `index` in the fallback chain, scaled to the requested size
+    /// for bitmap fonts.
+    pub fn size_metrics(&self, index: usize) -> Option<fts::FT_Size_Metrics> {
+        let face = self.faces().get(index).cloned()?;
+        let mut metrics = face.size_metrics()?;
+        if !face.is_scalable() && metrics.y_ppem > 0 {
+            let scale = self.size.get() as f64 / metrics.y_ppem as f64;
+            metrics.ascender = (metrics.ascender as f64 * scale) as fts::FT_Pos;
+            metrics.descender = (metrics.descender as f64 * scale) as fts::FT_Pos;
+            metrics.height = (metrics.height as f64 * scale) as fts::FT_Pos;
+        }
+        Some(metrics)
+    }
+
+    /// Selects the smallest strike at least as large as `size`, or the largest strike.
+    fn select_strike(face: &ft::Face, size: isize) -> ft::FtResult<()> {
+        let raw = face.raw();
+        let strikes: Vec<fts::FT_Pos> = (0..raw.num_fixed_sizes as isize)
+            .map(|i| unsafe { (*raw.available_sizes.offset(i)).y_ppem })
+            .collect();
+        let best = strikes.iter().enumerate()
+            .filter(|&(_, &ppem)| ppem >= (size * 64) as fts::FT_Pos)
+            .min_by_key(|&(_, &ppem)| ppem)
+            .or(strikes.iter().enumerate().max_by_key(|&(_, &ppem)| ppem))
+            .map(|(i, _)| i)
+            .ok_or(ft::Error::InvalidPixelSize)?;
+
+        let err = unsafe {
+            fts::FT_Select_Size(raw as *const fts::FT_FaceRec as fts::FT_Face, best as fts::FT_Int)
+        };
+        if err == fts::FT_Err_Ok {
+            Ok(())
+        } else {
+            Err(err.into())
         }
     }
 
+    fn faces(&self) -> Vec<&Rc<ft::Face<'static>>> {
+        let mut faces = vec![&self.face];
+        faces.extend(self.fallback_faces.iter());
+        faces
+    }
+
     pub fn set_color(&mut self, color: &RGBA) {
         self.color = *color;
     }
@@ -57,15 +229,80 @@ impl Renderer {
         };
     }
 
+    /// Synthesizes styles missing from the font: `bold_strength` pixels of outline emboldening,
+    /// an oblique shear of `oblique_angle` degrees and horizontal stretching in percent.
+    pub fn set_synthetic_style(&mut self, bold_strength: f64, oblique_angle: f64, stretch: u32) {
+        self.bold_strength = bold_strength;
+        self.oblique_angle = oblique_angle;
+        self.stretch = stretch;
+    }
+
+    /// Enables rendering of COLR layered glyphs with the given CPAL palette and of color bitmap
+    /// strikes. Color glyphs ignore the text color unless a layer asks for it.
+    pub fn set_color_glyphs(&mut self, enabled: bool, palette: usize) {
+        self.color_glyphs = enabled;
+        self.color_palette = palette;
+    }
+
+    /// Selects the OpenType features whose alternate glyphs replace those of the codepoints.
+    /// Only their single substitutions apply, no default features of shaping like the isolated
+    /// forms of Arabic letters.
+    pub fn set_features(&mut self, features: &[Feature]) {
+        self.features = features.to_vec();
+        self.alternates = self.faces().iter().map(|face| self.read_alternates(face)).collect();
+    }
+
+    fn read_alternates(&self, face: &ft::Face) -> HashMap<u32, u32> {
+        let tags: Vec<String> = self.features.iter().map(Feature::id).collect();
+        opentype::alternates(face, &tags)
+    }
+
+    /// Index into the fallback chain of the first face containing the codepoint and the index of
+    /// the glyph in that face, with the features applied.
+    pub fn glyph_index(&self, codepoint: usize) -> Option<(usize, u32)> {
+        self.faces().iter().enumerate()
+            .map(|(face_index, face)| (face_index, face.get_char_index(codepoint)))
+            .find(|&(_, glyph_index)| glyph_index != 0)
+            .map(|(face_index, glyph_index)| (face_index, self.alternate(face_index, glyph_index)))
+    }
+
+    /// The glyph the features replace a glyph of the face at `face_index` with, the glyph itself
+    /// if they don't.
+    pub fn alternate(&self, face_index: usize, glyph_index: u32) -> u32 {
+        self.alternates.get(face_index)
+            .and_then(|alternates| alternates.get(&glyph_index))
+            .cloned()
+            .unwrap_or(glyph_index)
+    }
+
+    /// Renders the codepoint with the first face of the fallback chain that contains it.
+    /// Fails with `InvalidCharacterCode` if no face does, instead of rendering the .notdef glyph.
     pub fn render(&self, codepoint: usize) -> ft::FtResult<RenderedGlyph> {
-        self.face.load_char(codepoint, ft::face::LoadFlag::DEFAULT)?;
+        let (face_index, glyph_index) = self.glyph_index(codepoint).ok_or(ft::Error::InvalidCharacterCode)?;
+        let mut rendered_glyph = self.rasterize(face_index, glyph_index)?;
+        rendered_glyph.codepoint = Some(codepoint);
+        Ok(rendered_glyph)
+    }
+
+    /// Renders a glyph of a face of the fallback chain by its index with `load_glyph`, e.g. an
+    /// alternate or one that only text shaping maps characters to.
+    pub fn render_glyph(&self, face_index: usize, glyph_index: u32) -> ft::FtResult<RenderedGlyph> {
+        let mut rendered_glyph = self.rasterize(face_index, glyph_index)?;
+        rendered_glyph.codepoints = self.codepoints(face_index, glyph_index);
+        Ok(rendered_glyph)
+    }
+
+    fn rasterize(&self, face_index: usize, glyph_index: u32) -> ft::FtResult<RenderedGlyph> {
+        let faces = self.faces();
+        let face = *faces.get(face_index).ok_or(ft::Error::InvalidFaceHandle)?;
+
+        if !face.is_scalable() {
+            return self.render_bitmap_strike(face, face_index, glyph_index);
+        }
 
-        let glyph = self.face.glyph().get_glyph()?;
+        let glyph = self.load_glyph(face, glyph_index)?;
 
         let mut bitmaps = Vec::new();
-        let mut glyph_bbox = None;
-        let mut glyph_advance_x = None;
-        let mut glyph_advance_y = None;
 
         if let Some(stroker) = self.outline_stroker.as_ref() {
             let glyph = unsafe {
@@ -78,42 +315,244 @@ impl Renderer {
             let bbox = glyph.get_cbox(fts::FT_GLYPH_BBOX_PIXELS);
             let bitmap_glyph = glyph.to_bitmap(ft::RenderMode::Normal, None)?;
 
-            glyph_bbox = Some(bbox);
-            glyph_advance_x = Some(glyph.advance_x());
-            glyph_advance_y = Some(glyph.advance_y());
-
-            bitmaps.push((bitmap_glyph, bbox, &self.outline_color));
+            bitmaps.push((bitmap_glyph, bbox, self.outline_color));
         }
 
-        {
+        let layers = if self.color_glyphs {
+            self.color_layers[face_index].as_ref().and_then(|c| c.layers(glyph_index, self.color_palette, &self.color))
+        } else {
+            None
+        };
+
+        if let Some(layers) = layers {
+            for (layer_index, color) in layers {
+                let layer = self.load_glyph(face, layer_index)?;
+                let bbox = layer.get_cbox(fts::FT_GLYPH_BBOX_PIXELS);
+                let bitmap_glyph = layer.to_bitmap(ft::RenderMode::Normal, None)?;
+
+                bitmaps.push((bitmap_glyph, bbox, color));
+            }
+        } else {
             let bbox = glyph.get_cbox(fts::FT_GLYPH_BBOX_PIXELS);
             let bitmap_glyph = glyph.to_bitmap(ft::RenderMode::Normal, None)?;
 
-            glyph_bbox = glyph_bbox.or(Some(bbox));
-            glyph_advance_x = glyph_advance_x.or(Some(glyph.advance_x()));
-            glyph_advance_y = glyph_advance_y.or(Some(glyph.advance_y()));
-
-            bitmaps.push((bitmap_glyph, bbox, &self.color));
+            bitmaps.push((bitmap_glyph, bbox, self.color));
         }
 
+        let bounding_box = Renderer::bounding_box(&bitmaps);
         let surface = Renderer::bitmaps_to_surface(bitmaps)?;
+        let (surface, offset) = Renderer::trim(surface, (bounding_box.xMin as i32, bounding_box.yMax as i32))?;
+
+        Ok(RenderedGlyph {
+            surface: surface,
+            codepoint: None,
+            face: face_index,
+            glyph_index: glyph_index,
+            image: false,
+            codepoints: Vec::new(),
+            advance: ((glyph.advance_x() >> 16) as i32, (glyph.advance_y() >> 16) as i32),
+            offset: offset,
+        })
+    }
+
+    /// Kerning between two codepoints in pixels, 0 if they are rendered by different faces. It
+    /// comes from the pair adjustments of the `kern` feature in the GPOS table, or from the
+    /// legacy `kern` table for faces without them. Contextual kerning and kerning between glyphs
+    /// of different faces can't be expressed as pairs of codepoints.
+    pub fn kerning(&self, left: usize, right: usize) -> i32 {
+        let faces = self.faces();
+
+        match (self.glyph_index(left), self.glyph_index(right)) {
+            (Some((l, left_glyph)), Some((r, right_glyph))) if l == r => {
+                if let Some(kerning) = self.pair_kernings[l].as_ref() {
+                    let units = kerning.get(left_glyph, right_glyph);
+                    (units as f64 * self.size.get() as f64 / faces[l].em_size() as f64).round() as i32
+                } else if faces[l].has_kerning() {
+                    faces[l].get_kerning(left_glyph, right_glyph, ft::face::KerningMode::KerningDefault)
+                        .map(|v| (v.x >> 6) as i32)
+                        .unwrap_or(0)
+                } else {
+                    0
+                }
+            },
+            _ => 0,
+        }
+    }
+
+    /// Loads the outline of a glyph with the synthetic styles applied.
+    fn load_glyph(&self, face: &ft::Face, glyph_index: u32) -> ft::FtResult<ft::Glyph> {
+        face.load_glyph(glyph_index, ft::face::LoadFlag::DEFAULT)?;
+        self.embolden(face)?;
+
+        let glyph = face.glyph().get_glyph()?;
+        if self.oblique_angle != 0.0 || self.stretch != 100 {
+            // Transforms the advance as well, the shear leaves the horizontal advance untouched
+            glyph.transform(Some(ft::Matrix {
+                xx: (self.stretch as f64 / 100.0 * 65536.0) as fts::FT_Fixed,
+                xy: (self.oblique_angle.to_radians().tan() * 65536.0) as fts::FT_Fixed,
+                yx: 0,
+                yy: 0x10000,
+            }), None)?;
+        }
+
+        Ok(glyph)
+    }
+
+    /// Coverage of a pixel of a grayscale or monochrome bitmap, scaled to 0-255. Pixels of the
+    /// packed formats are stored starting at the most significant bits.
+    fn coverage(buffer: &[u8], pitch: usize, pixel_mode: ft::bitmap::PixelMode, x: usize, y: usize) -> ft::FtResult<u8> {
+        let row = &buffer[y * pitch..];
+        match pixel_mode {
+            ft::bitmap::PixelMode::Gray => Ok(row[x]),
+            ft::bitmap::PixelMode::Mono => Ok(if row[x / 8] & (0x80 >> (x % 8)) != 0 { 255 } else { 0 }),
+            ft::bitmap::PixelMode::Gray2 => Ok((row[x / 4] >> (6 - 2 * (x % 4)) & 0x3) * 85),
+            ft::bitmap::PixelMode::Gray4 => Ok((row[x / 2] >> (4 - 4 * (x % 2)) & 0xF) * 17),
+            _ => Err(ft::Error::UnimplementedFeature),
+        }
+    }
+
+    /// Renders a glyph of a bitmap font, scaled from the selected strike to the requested size
+    /// like the size metrics are. Color strikes (CBDT or sbix) keep their colors when color
+    /// glyphs are enabled, all others are drawn in the text color. Borders and synthetic styles
+    /// need an outline and are left out.
+    fn render_bitmap_strike(&self, face: &ft::Face, face_index: usize, glyph_index: u32) -> ft::FtResult<RenderedGlyph> {
+        let flags = if self.color_glyphs { ft::face::LoadFlag::COLOR } else { ft::face::LoadFlag::DEFAULT };
+        face.load_glyph(glyph_index, flags)?;
+
+        let slot = face.glyph();
+        let bitmap = slot.bitmap();
+        let strike_size = face.size_metrics().map(|m| m.y_ppem as f64).unwrap_or(self.size.get() as f64);
+        let scale = self.size.get() as f64 / strike_size;
+
+        let width = bitmap.width();
+        let height = bitmap.rows();
+        let stride = Format::ARgb32.stride_for_width(width as u32).unwrap_or(width * 4);
+        let mut data = vec![0u8; (stride * height) as usize];
+        let buffer = bitmap.buffer();
+        let pitch = bitmap.pitch().abs() as usize;
+        let pixel_mode = bitmap.pixel_mode()?;
+
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let offset = y * stride as usize + x * 4;
+                match pixel_mode {
+                    ft::bitmap::PixelMode::Bgra => {
+                        // Already premultiplied BGRA, same as cairo's native byte order
+                        data[offset..offset + 4].copy_from_slice(&buffer[y * pitch + x * 4..y * pitch + x * 4 + 4]);
+                    },
+                    _ => {
+                        let alpha = Renderer::coverage(buffer, pitch, pixel_mode, x, y)? as f64 / 255.0 * self.color.alpha;
+                        data[offset + 0] = (self.color.blue * alpha * 255.0) as u8;
+                        data[offset + 1] = (self.color.green * alpha * 255.0) as u8;
+                        data[offset + 2] = (self.color.red * alpha * 255.0) as u8;
+                        data[offset + 3] = (alpha * 255.0) as u8;
+                    },
+                }
+            }
+        }
+
+        let strike = ImageSurface::create_for_data(data.into_boxed_slice(), |_| (), Format::ARgb32, width, height, stride)
+            .map_err(|_| ft::Error::Unknown)?;
+        let scaled_width = (width as f64 * scale).ceil() as i32;
+        let scaled_height = (height as f64 * scale).ceil() as i32;
+        let surface = ImageSurface::create(Format::ARgb32, scaled_width, scaled_height).map_err(|_| ft::Error::Unknown)?;
+        {
+            let context = Context::new(&surface);
+            context.scale(scale, scale);
+            context.set_source_surface(&strike, 0.0, 0.0);
+            context.paint();
+        }
+
+        let offset = ((slot.bitmap_left() as f64 * scale).round() as i32, (slot.bitmap_top() as f64 * scale).round() as i32);
+        let (surface, offset) = Renderer::trim(surface, offset)?;
 
         Ok(RenderedGlyph {
             surface: surface,
-            codepoint: codepoint,
-            advance: ((glyph_advance_x.unwrap() >> 16) as i32, (glyph_advance_y.unwrap() >> 16) as i32),
-            offset: (glyph_bbox.unwrap().xMin as i32, glyph_bbox.unwrap().yMax as i32),
+            codepoint: None,
+            face: face_index,
+            glyph_index: glyph_index,
+            image: false,
+            codepoints: Vec::new(),
+            advance: (((slot.advance().x >> 6) as f64 * scale).round() as i32, ((slot.advance().y >> 6) as f64 * scale).round() as i32),
+            offset: offset,
         })
     }
 
-    fn bitmaps_to_surface(bitmaps: Vec<(ft::BitmapGlyph, ft::BBox, &RGBA)>) -> ft::FtResult<ImageSurface> {
+    /// Emboldens the outline in the glyph slot and widens the advance by the same amount.
+    fn embolden(&self, face: &ft::Face) -> ft::FtResult<()> {
+        if self.bold_strength <= 0.0 {
+            return Ok(());
+        }
+
+        let strength = (self.bold_strength * 64.0) as fts::FT_Pos;
+        let err = unsafe {
+            let slot = face.glyph().raw() as *const fts::FT_GlyphSlotRec as *mut fts::FT_GlyphSlotRec;
+            if (*slot).format != fts::FT_GLYPH_FORMAT_OUTLINE {
+                return Ok(());
+            }
+            (*slot).advance.x += strength;
+            FT_Outline_Embolden(&mut (*slot).outline, strength)
+        };
+
+        if err == fts::FT_Err_Ok {
+            Ok(())
+        } else {
+            Err(err.into())
+        }
+    }
+
+    /// Crops fully transparent rows and columns from the borders of a rendered glyph and moves
+    /// the offset of its top-left corner along. Glyphs without any visible pixel become empty.
+    fn trim(mut surface: ImageSurface, offset: (i32, i32)) -> ft::FtResult<(ImageSurface, (i32, i32))> {
+        let width = surface.get_width();
+        let height = surface.get_height();
+        let stride = surface.get_stride();
+        let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+        {
+            let data = surface.get_data().map_err(|_| ft::Error::Unknown)?;
+            for y in 0..height {
+                for x in 0..width {
+                    if data[(y * stride + x * 4 + 3) as usize] != 0 {
+                        left = cmp::min(left, x);
+                        top = cmp::min(top, y);
+                        right = cmp::max(right, x + 1);
+                        bottom = cmp::max(bottom, y + 1);
+                    }
+                }
+            }
+        }
+
+        if left >= right {
+            let empty = ImageSurface::create(Format::ARgb32, 0, 0).map_err(|_| ft::Error::Unknown)?;
+            return Ok((empty, (0, 0)));
+        }
+        if (left, top, right, bottom) == (0, 0, width, height) {
+            return Ok((surface, offset));
+        }
+
+        let trimmed = ImageSurface::create(Format::ARgb32, right - left, bottom - top).map_err(|_| ft::Error::Unknown)?;
+        {
+            let context = Context::new(&trimmed);
+            context.set_operator(Operator::Source);
+            context.set_source_surface(&surface, -left as f64, -top as f64);
+            context.paint();
+        }
+        Ok((trimmed, (offset.0 + left, offset.1 - top)))
+    }
+
+    fn bounding_box(bitmaps: &Vec<(ft::BitmapGlyph, ft::BBox, RGBA)>) -> ft::BBox {
         let min_box = ft::BBox {
             xMin: c_long::max_value(),
             yMin: c_long::max_value(),
             xMax: c_long::min_value(),
             yMax: c_long::min_value(),
         };
-        let bounding_box = bitmaps.iter().fold(min_box, |acc, &(_,bbox,_)| { Renderer::union(&acc, &bbox) });
+        bitmaps.iter().fold(min_box, |acc, &(_,bbox,_)| { Renderer::union(&acc, &bbox) })
+    }
+
+    /// Draws the bitmaps over each other in order, each in its color, into premultiplied BGRA.
+    fn bitmaps_to_surface(bitmaps: Vec<(ft::BitmapGlyph, ft::BBox, RGBA)>) -> ft::FtResult<ImageSurface> {
+        let bounding_box = Renderer::bounding_box(&bitmaps);
 
         let width = bounding_box.xMax - bounding_box.xMin;
         let height = bounding_box.yMax - bounding_box.yMin;
@@ -123,40 +562,21 @@ impl Renderer {
         for (bitmap, bbox, color) in bitmaps {
             let b = bitmap.bitmap();
             let buffer = b.buffer();
-            let red = color.red as f32;
-            let green = color.green as f32;
-            let blue = color.blue as f32;
+            let pitch = b.pitch().abs() as usize;
+            let pixel_mode = b.pixel_mode()?;
 
-            let bitmap_width = bbox.xMax - bbox.xMin;
-            let bitmap_height = bbox.yMax - bbox.yMin;
+            let bitmap_width = cmp::min(bbox.xMax - bbox.xMin, b.width() as c_long);
+            let bitmap_height = cmp::min(bbox.yMax - bbox.yMin, b.rows() as c_long);
 
             let offset_x = bbox.xMin - bounding_box.xMin;
             let offset_y = -(bbox.yMax - bounding_box.yMax);
 
             for y in 0..bitmap_height {
                 for x in 0..bitmap_width {
-                    let value = buffer[(y * bitmap_width + x) as usize];
-
-                    let offset = ((y + offset_y) * (width*4) + (x + offset_x) * 4) as usize;
+                    let value = Renderer::coverage(buffer, pitch, pixel_mode, x as usize, y as usize)?;
                     if value > 0 {
-                        let old_value = out[offset + 3];
-                        let source_alpha = value as f32 / 255.0;
-                        if old_value > 0.0 {
-                            let dest_blue = out[offset + 0];
-                            let dest_green = out[offset + 1];
-                            let dest_red = out[offset + 2];
-                            let dest_alpha = out[offset + 3]; 
-                            let new_alpha = source_alpha + dest_alpha * (1.0 - source_alpha);
-                            out[offset + 0] = (blue + dest_blue * (1.0 - source_alpha)) / new_alpha;
-                            out[offset + 1] = (green + dest_green * (1.0 - source_alpha)) / new_alpha;
-                            out[offset + 2] = (red + dest_red * (1.0 - source_alpha)) / new_alpha;
-                            out[offset + 3] = new_alpha;
-                        } else {
-                            out[offset + 0] = blue;
-                            out[offset + 1] = green;
-                            out[offset + 2] = red;
-                            out[offset + 3] = source_alpha;
-                        }
+                        let offset = ((y + offset_y) * (width*4) + (x + offset_x) * 4) as usize;
+                        Renderer::blend_over(&mut out[offset..offset + 4], value, &color);
                     }
                 }
             }
@@ -165,6 +585,16 @@ impl Renderer {
         Renderer::f32_image_to_image_surface(&out, width as u32, height as u32)
     }
 
+    /// Draws a pixel of `coverage` in `color` over a premultiplied BGRA pixel, the alpha of the
+    /// color weakens the coverage.
+    fn blend_over(pixel: &mut [f32], coverage: u8, color: &RGBA) {
+        let alpha = (coverage as f64 / 255.0 * color.alpha) as f32;
+        let source = [color.blue as f32 * alpha, color.green as f32 * alpha, color.red as f32 * alpha, alpha];
+        for (dest, source) in pixel.iter_mut().zip(&source) {
+            *dest = source + *dest * (1.0 - alpha);
+        }
+    }
+
     fn f32_image_to_image_surface(buffer: &Vec<f32>, width: u32, height: u32) -> ft::FtResult<ImageSurface> {
         let stride = Format::ARgb32.stride_for_width(width).unwrap_or(width as i32 * 4) as u32;
         let out_size = (stride * height) as usize;
@@ -262,3 +692,47 @@ impl Drop for Stroker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_coverage_of_packed_bitmaps() {
+        let mono = [0b1010_0000, 0b0000_0001];
+        let covered: Vec<u8> = (0..16).map(|x| Renderer::coverage(&mono, 2, ft::bitmap::PixelMode::Mono, x, 0).unwrap()).collect();
+        assert_eq!(&covered[..4], &[255, 0, 255, 0]);
+        assert_eq!(covered[15], 255);
+
+        let gray2 = [0b11_10_01_00];
+        let covered: Vec<u8> = (0..4).map(|x| Renderer::coverage(&gray2, 1, ft::bitmap::PixelMode::Gray2, x, 0).unwrap()).collect();
+        assert_eq!(covered, vec![255, 170, 85, 0]);
+
+        let gray4 = [0x0F, 0xF0, 0x80];
+        assert_eq!(Renderer::coverage(&gray4, 1, ft::bitmap::PixelMode::Gray4, 1, 0).unwrap(), 255);
+        assert_eq!(Renderer::coverage(&gray4, 1, ft::bitmap::PixelMode::Gray4, 0, 1).unwrap(), 255);
+        assert_eq!(Renderer::coverage(&gray4, 1, ft::bitmap::PixelMode::Gray, 0, 2).unwrap(), 0x80);
+        assert!(Renderer::coverage(&gray4, 1, ft::bitmap::PixelMode::Lcd, 0, 0).is_err());
+    }
+
+    #[test]
+    fn blends_layers_over_each_other_premultiplied() {
+        let red = RGBA { red: 1.0, green: 0.0, blue: 0.0, alpha: 1.0 };
+        let half_blue = RGBA { red: 0.0, green: 0.0, blue: 1.0, alpha: 0.5 };
+
+        let mut pixel = [0.0f32; 4];
+        Renderer::blend_over(&mut pixel, 255, &half_blue);
+        assert_eq!(pixel, [0.5, 0.0, 0.0, 0.5]);
+
+        let mut pixel = [0.0f32; 4];
+        Renderer::blend_over(&mut pixel, 255, &red);
+        Renderer::blend_over(&mut pixel, 255, &half_blue);
+        assert_eq!(pixel, [0.5, 0.0, 0.5, 1.0]);
+
+        // A fifth of the coverage of a half transparent color covers a tenth
+        let mut pixel = [0.0f32; 4];
+        Renderer::blend_over(&mut pixel, 255, &red);
+        Renderer::blend_over(&mut pixel, 51, &half_blue);
+        assert!((pixel[0] - 0.1).abs() < 1e-6 && (pixel[2] - 0.9).abs() < 1e-6 && pixel[3] == 1.0);
+    }
+}
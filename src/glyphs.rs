@@ -1,22 +1,163 @@
 use cairo;
+use ft;
+use ft::freetype_sys as fts;
 use std::cmp;
+use std::f64;
 use std::fs::{File};
 use std::io;
-use std::path::{Path};
+use std::path::{Path, PathBuf};
+use std::rc::{Rc};
 
+use export::{ExportFormat};
 use font;
-use glyph::{Renderer};
-use render_settings::{RenderSettings};
+use glyph::{RenderedGlyph, Renderer};
+use godot;
+use grid::{self, Grid};
+use hiero;
+use image::{Extrude, Image};
+use json;
+use packer::{Packer};
+use render_settings::{FontVariant, RenderSettings};
+use shaping::{self, Feature, Position};
+use source;
+use text_mesh_pro;
 
+#[derive(Clone)]
 pub struct GlyphInfo {
-    codepoint: usize,
-    x: u32,
-    y: u32,
+    /// Unicode codepoint, `None` for glyphs added by their index or by text shaping
+    pub codepoint: Option<usize>,
+    /// Index into `RenderSettings::variants` the glyph was rendered with
+    pub variant: usize,
+    /// Index into the fallback chain of the face and index of the glyph in that face
+    pub face: usize,
+    pub glyph_index: u32,
+    /// An image instead of a glyph of a face
+    pub image: bool,
+    /// Codepoints the cmap of the face maps to a glyph rendered by index
+    pub codepoints: Vec<usize>,
+    pub page: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Left edge of the bitmap inside the padding, relative to the text cursor
+    pub xoffset: i32,
+    /// Top edge of the bitmap inside the padding, above the baseline
+    pub yoffset: i32,
+    /// Advance of the text cursor, the padding is not part of it
+    pub xadvance: i32,
+    /// Stored turned 90 degrees clockwise, `width` and `height` are the size before turning
+    pub rotated: bool,
+}
+
+impl GlyphInfo {
+    /// Position and size of the pixels the glyph covers in its page
+    pub fn rect(&self) -> (u32, u32, u32, u32) {
+        if self.rotated {
+            (self.x, self.y, self.height, self.width)
+        } else {
+            (self.x, self.y, self.width, self.height)
+        }
+    }
+}
+
+/// Line metrics of a font variant in pixels. When glyphs come from fallback faces the metrics
+/// cover those faces as well, so all glyphs fit between the top and bottom of a line.
+pub struct LineMetrics {
+    pub ascender: i32,
+    pub descender: i32,
+    pub height: i32,
+}
+
+pub struct KerningInfo {
+    variant: usize,
+    first: usize,
+    second: usize,
+    amount: i32,
+}
+
+/// Glyphs text shaping maps a run of codepoints to, where they differ from the glyphs of the
+/// single codepoints, e.g. a ligature or the contextual form of an Arabic letter. The same run
+/// can map to other glyphs between other characters.
+pub struct SubstitutionInfo {
+    variant: usize,
+    codepoints: Vec<usize>,
+    before: Option<usize>,
+    after: Option<usize>,
+    glyphs: Vec<u32>,
+    positions: Vec<Position>,
+}
+
+/// What to place next in the atlas
+enum Request {
+    NewRow,
+    Codepoint(usize),
+    /// Glyph of the primary face by its index
+    Glyph(u32),
+    /// Index into `RenderSettings::image_glyphs`
+    Image(usize),
+}
+
+/// Pixels of a glyph drawn into the pages and where, so glyphs with identical bitmaps can share
+/// the rect.
+struct PackedBitmap {
+    data: Vec<u8>,
     width: u32,
     height: u32,
-    xoffset: i32,
-    yoffset: i32,
-    xadvance: i32,
+    page: u32,
+    left: i32,
+    top: i32,
+    rotated: bool,
+}
+
+/// Glyphs of a variant to put in a grid and the room for it.
+struct GridPlacement<'a> {
+    glyphs: &'a [RenderedGlyph],
+    variant: usize,
+    metrics: &'a LineMetrics,
+    page_width: i32,
+    page_height: i32,
+}
+
+/// Grids are meant for contiguous ranges like ASCII, codepoints whose cell lies beyond this many
+/// pages are left out instead of adding pages for them.
+const MAX_GRID_PAGES: i32 = 16;
+
+/// Rendered glyphs of all font variants packed into shared pages.
+pub struct Atlas {
+    pub glyphs: Vec<GlyphInfo>,
+    pub pages: Vec<Image>,
+    /// Line metrics for each of `RenderSettings::variants`
+    pub metrics: Vec<LineMetrics>,
+    pub kernings: Vec<KerningInfo>,
+    /// Codepoints of the text that no face of the fallback chain contains or that are outside
+    /// the grid
+    pub missing: Vec<usize>,
+    /// Cell layout for each of `RenderSettings::variants`, empty unless glyphs are in a grid
+    pub grids: Vec<Grid>,
+    /// Shaped codepoint runs of all variants, empty unless text shaping is enabled
+    pub substitutions: Vec<SubstitutionInfo>,
+}
+
+impl Atlas {
+    /// The glyph of this atlas that corresponds to a glyph of an earlier one, rendered for the
+    /// same variant and codepoint, or glyph index if it has no codepoint.
+    pub fn same_glyph(&self, glyph: &GlyphInfo) -> Option<&GlyphInfo> {
+        self.glyphs.iter().find(|g| {
+            g.variant == glyph.variant && match glyph.codepoint {
+                Some(_) => g.codepoint == glyph.codepoint,
+                None => g.codepoint.is_none() && g.face == glyph.face && g.glyph_index == glyph.glyph_index,
+            }
+        })
+    }
+
+    /// The glyph covering the pixel of a page, the first one if several glyphs share the rect.
+    pub fn glyph_at(&self, page: u32, x: u32, y: u32) -> Option<&GlyphInfo> {
+        self.glyphs.iter().find(|g| {
+            let (left, top, width, height) = g.rect();
+            g.page == page && g.width > 0 && x >= left && x < left + width && y >= top && y < top + height
+        })
+    }
 }
 
 pub struct Glyphs {
@@ -27,127 +168,624 @@ impl Glyphs {
         Glyphs { }
     }
 
-    pub fn write_to_file<P: AsRef<Path>>(&self, path: P, render_settings: &RenderSettings, width: i32, height: i32) -> io::Result<()> {
-        let png_filepath = path.as_ref().with_extension("png");
-        let fnt_filepath = path.as_ref().with_extension("fnt");
-        let (info, surface) = self.render_to_surface(render_settings, width, height);
-        let mut png_file = File::create(&png_filepath)?;
-        match surface.write_to_png(&mut png_file) {
-            Ok(_) => (),
-            Err(_) => return Err(io::Error::from(io::ErrorKind::Other)),
+    /// Writes the atlas pages and font files, returns the codepoints that were left out because
+    /// no face contains them.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P, render_settings: &RenderSettings, width: i32, height: i32, format: ExportFormat) -> io::Result<Vec<usize>> {
+        if render_settings.rotate_glyphs && !format.supports_rotation() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} can't describe rotated glyphs", format.name())));
+        }
+
+        let path = &format.strip_extension(path.as_ref());
+        let atlas = self.render_to_surface(render_settings, width, height)?;
+
+        // Source code embeds the pages instead of referencing image files
+        let mut page_files = Vec::new();
+        let source_pages = if format.embeds_pages() {
+            page_files = (0..atlas.pages.len()).map(|id| format!("page_{}", id)).collect();
+            Some(source::Pages::from_images(&atlas.pages, render_settings.source_bits_per_pixel))
+        } else {
+            let image_format = render_settings.image_format;
+            for (id, image) in atlas.pages.iter().enumerate() {
+                let image_filepath = if atlas.pages.len() == 1 {
+                    path.with_extension(image_format.extension())
+                } else {
+                    Glyphs::sibling_path(path, &id.to_string(), image_format.extension())
+                };
+                let mut image_file = File::create(&image_filepath)?;
+                image.write(image_format, render_settings.pixel_format, render_settings.mip_levels > 0, &mut image_file)?;
+                page_files.push(image_filepath.file_name().expect("").to_string_lossy().to_string());
+            }
+            None
+        };
+
+        let variants = render_settings.variants();
+        let sizes_per_face = variants.len() / (render_settings.extra_faces.len() + 1);
+        let font_files = self.font_files(&atlas, render_settings, width, height, &page_files);
+
+        for (index, (variant, font_file)) in variants.iter().zip(font_files).enumerate() {
+            let extension = format.extension();
+            let descriptor_filepath = if variants.len() == 1 {
+                path.with_extension(extension)
+            } else if !render_settings.extra_faces.is_empty() {
+                Glyphs::sibling_path(path, &format!("{}_{}", index / sizes_per_face, variant.font_size), extension)
+            } else {
+                Glyphs::sibling_path(path, &variant.font_size.to_string(), extension)
+            };
+            let mut descriptor_file = File::create(&descriptor_filepath)?;
+
+            match format {
+                ExportFormat::BMFont => {
+                    font_file.write(&mut descriptor_file)?;
+                    if render_settings.hiero_settings {
+                        let mut settings_file = File::create(descriptor_filepath.with_extension("hiero"))?;
+                        hiero::write(&font_file, render_settings, &mut settings_file)?;
+                    }
+                },
+                ExportFormat::Json => json::write(&font_file, &mut descriptor_file)?,
+                ExportFormat::Godot => godot::write(&font_file, &mut descriptor_file)?,
+                ExportFormat::TextMeshPro => text_mesh_pro::write(&font_file, &mut descriptor_file)?,
+                ExportFormat::BMFontXml => font_file.write_xml(&mut descriptor_file)?,
+                ExportFormat::CSource => {
+                    let name = source::identifier(&descriptor_filepath.file_stem().expect("").to_string_lossy());
+                    source::write_c(&font_file, source_pages.as_ref().expect("Pages embedded"), &name, &mut descriptor_file)?;
+                },
+                ExportFormat::RustSource => source::write_rust(&font_file, source_pages.as_ref().expect("Pages embedded"), &mut descriptor_file)?,
+            }
+
+            if let Some(grid) = atlas.grids.get(index) {
+                let mut grid_file = File::create(descriptor_filepath.with_extension("grid.json"))?;
+                grid::write(grid, &mut grid_file)?;
+            }
         }
-        let mut fnt_file = File::create(&fnt_filepath)?;
 
+        Ok(atlas.missing)
+    }
+
+    /// Builds the font descriptor of each of `RenderSettings::variants`, referencing the atlas
+    /// pages by the given file names. The metrics follow AngelCode's BMFont: offsets point at the
+    /// top-left corner of the padded rect, relative to the text cursor and the top of the line,
+    /// while the advance, base and line height are those of the font. Padding and border only
+    /// show up in the info tag. The glyph and kerning adjustments of the render settings are
+    /// applied on top.
+    pub fn font_files(&self, atlas: &Atlas, render_settings: &RenderSettings, width: i32, height: i32, page_files: &[String]) -> Vec<font::FontFile> {
         let padding = render_settings.letter_padding as u32;
         let spacing = render_settings.letter_spacing as u32;
-        let metrics = render_settings.face.size_metrics().expect("Need metrics");
-        let line_height = metrics.height as u32 / 64 + 2 * padding;
-        let base = metrics.ascender as i32 / 64 + padding as i32;
-
-        let font_file = font::FontFile {
-            info: font::InfoTag {
-                face: "".to_string(),
-                size: render_settings.font_size as u32,
-                bold: false,
-                italic: false,
-                charset: "".to_string(),
-                unicode: false,
-                stretch_h: 100,
-                smooth: false,
-                aa: false,
-                padding: [padding, padding, padding, padding],
-                spacing: [spacing, spacing],
-                outline: 0,
-            },
-            common: font::CommonTag {
-                line_height: line_height,
-                base: base,
-                scale_w: width as u32,
-                scale_h: height as u32,
-                pages: 1,
-                packed: false,
-                alpha_channel: 0,
-                red_channel: 0,
-                green_channel: 0,
-                blue_channel: 0,
-            },
-            page: font::PageTag {
-                id: 0,
-                file: png_filepath.file_name().expect("").to_string_lossy().to_string(),
-            },
-            chars: info.iter().map(|g| {
-                font::CharTag {
-                    id: g.codepoint,
-                    x: g.x,
-                    y: g.y,
-                    width: g.width,
-                    height: g.height,
-                    xoffset: g.xoffset,
-                    yoffset: (line_height as i32) - g.yoffset,
-                    xadvance: g.xadvance,
-                    page: 0,
-                    chnl: 15,
+
+        render_settings.variants().iter().enumerate().map(|(index, variant)| {
+            let metrics = &atlas.metrics[index];
+            let style_flags = variant.face.raw().style_flags;
+            let base = metrics.ascender;
+            let glyphs: Vec<&GlyphInfo> = atlas.glyphs.iter().filter(|g| g.variant == index).collect();
+            let char_tag = |g: &GlyphInfo, id: usize| font::CharTag {
+                id: id,
+                x: g.x,
+                y: g.y,
+                width: g.width,
+                height: g.height,
+                // Empty glyphs have no rect to place
+                xoffset: if g.width == 0 { 0 } else { g.xoffset - padding as i32 },
+                yoffset: if g.width == 0 { 0 } else { base - g.yoffset - padding as i32 },
+                xadvance: g.xadvance,
+                page: g.page,
+                chnl: 15,
+                rotated: g.rotated,
+            };
+
+            // Shaped text and glyphs requested by index refer to glyphs of the primary face by
+            // their index, each listed once
+            let mut indexed_glyphs: Vec<&GlyphInfo> = Vec::new();
+            if render_settings.shape_text || !render_settings.glyph_indices.is_empty() {
+                for &g in glyphs.iter().filter(|g| g.face == 0) {
+                    if !g.image && !indexed_glyphs.iter().any(|s| s.glyph_index == g.glyph_index) {
+                        indexed_glyphs.push(g);
+                    }
                 }
-            }).collect(),
-        };
+            }
+
+            let mut font_file = font::FontFile {
+                info: font::InfoTag {
+                    face: render_settings.face_name(&variant.face),
+                    size: variant.font_size as u32,
+                    bold: render_settings.synthetic_bold > 0.0 || style_flags & fts::FT_STYLE_FLAG_BOLD != 0,
+                    italic: render_settings.synthetic_oblique != 0.0 || style_flags & fts::FT_STYLE_FLAG_ITALIC != 0,
+                    charset: "".to_string(),
+                    unicode: false,
+                    stretch_h: render_settings.stretch,
+                    smooth: false,
+                    aa: false,
+                    padding: [padding, padding, padding, padding],
+                    spacing: [spacing, spacing],
+                    outline: render_settings.border_width as u32,
+                },
+                common: font::CommonTag {
+                    line_height: metrics.height as u32,
+                    base: base,
+                    scale_w: width as u32,
+                    scale_h: height as u32,
+                    pages: page_files.len() as u32,
+                    packed: false,
+                    alpha_channel: 0,
+                    red_channel: 0,
+                    green_channel: 0,
+                    blue_channel: 0,
+                },
+                pages: page_files.iter().enumerate().map(|(id, file)| {
+                    font::PageTag {
+                        id: id as u32,
+                        file: file.clone(),
+                    }
+                }).collect(),
+                chars: glyphs.iter().filter_map(|g| g.codepoint.map(|codepoint| char_tag(g, codepoint))).collect(),
+                kernings: atlas.kernings.iter().filter(|k| k.variant == index).map(|k| {
+                    font::KerningTag {
+                        first: k.first,
+                        second: k.second,
+                        amount: k.amount,
+                    }
+                }).collect(),
+                glyphs: indexed_glyphs.iter().map(|g| {
+                    font::GlyphTag {
+                        char: char_tag(g, g.glyph_index as usize),
+                        codepoints: g.codepoints.clone(),
+                    }
+                }).collect(),
+                substitutions: atlas.substitutions.iter().filter(|s| s.variant == index).map(|s| {
+                    font::SubstitutionTag {
+                        codepoints: s.codepoints.clone(),
+                        before: s.before,
+                        after: s.after,
+                        glyphs: s.glyphs.clone(),
+                        offsets: s.positions.iter().map(|p| p.offset).collect(),
+                        advances: s.positions.iter().map(|p| p.advance).collect(),
+                    }
+                }).collect(),
+            };
+            Glyphs::apply_adjustments(&mut font_file, render_settings);
+            font_file
+        }).collect()
+    }
 
-        font_file.write(&mut fnt_file)?;
+    /// Adds the glyph adjustments to the metrics of the chars and the kerning adjustments to the
+    /// kerning pairs, pairs of chars the font doesn't kern get added.
+    fn apply_adjustments(font_file: &mut font::FontFile, render_settings: &RenderSettings) {
+        for char in &mut font_file.chars {
+            let adjustment = render_settings.glyph_adjustment(char.id);
+            char.xoffset += adjustment.xoffset;
+            char.yoffset += adjustment.yoffset;
+            char.xadvance += adjustment.xadvance;
+        }
 
-        Ok(())
+        for adjustment in &render_settings.kerning_adjustments {
+            let has_pair = font_file.chars.iter().any(|c| c.id == adjustment.first) && font_file.chars.iter().any(|c| c.id == adjustment.second);
+            if !has_pair {
+                continue
+            }
+            let kerning = font_file.kernings.iter_mut().find(|k| k.first == adjustment.first && k.second == adjustment.second);
+            match kerning {
+                Some(kerning) => kerning.amount += adjustment.amount,
+                None => font_file.kernings.push(font::KerningTag {
+                    first: adjustment.first,
+                    second: adjustment.second,
+                    amount: adjustment.amount,
+                }),
+            }
+        }
+        font_file.kernings.retain(|k| k.amount != 0);
     }
 
-    pub fn render_to_surface(&self, render_settings: &RenderSettings, width: i32, height: i32) -> (Vec<GlyphInfo>, cairo::ImageSurface) {
+    /// Renders and packs the glyphs of all variants. Fails if FreeType can't render a glyph the
+    /// font contains.
+    pub fn render_to_surface(&self, render_settings: &RenderSettings, width: i32, height: i32) -> io::Result<Atlas> {
         let letter_padding = render_settings.letter_padding as i32;
-        let letter_spacing = render_settings.letter_spacing as i32;
-        let mut left: i32 = 0;
-        let mut top: i32 = 0;
-        let mut max_row_height: i32 = 0;
+        let mut packer = Packer::new(width, height, render_settings.letter_spacing as i32);
+        if render_settings.mip_levels > 0 {
+            packer.align_for_mip_levels(render_settings.mip_levels);
+        }
+        packer.set_rotation(render_settings.rotate_glyphs);
+        let mut renderers: Vec<(Rc<_>, Renderer)> = Vec::new();
 
-        let mut renderer = Renderer::new(&render_settings.library, &render_settings.face);
-        renderer.set_color(&render_settings.font_color);
-        renderer.set_outline(&render_settings.border_color, render_settings.border_width);
+        let mut info: Vec<GlyphInfo> = Vec::new();
+        let mut pages = vec![Glyphs::create_page(width, height)];
+        let mut metrics = Vec::new();
+        let mut kernings = Vec::new();
+        let mut missing = Vec::new();
+        let mut grids = Vec::new();
+        let mut substitutions = Vec::new();
+        let mut bitmaps: Vec<PackedBitmap> = Vec::new();
 
-        let mut info = Vec::new();
+        for (index, variant) in render_settings.variants().iter().enumerate() {
+            let renderer = Glyphs::renderer_for(&mut renderers, render_settings, variant);
+            renderer.set_char_size(variant.font_size).map_err(|err| render_error(&format!("{} px", variant.font_size), err))?;
+            packer.new_row();
 
-        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).unwrap();
-        let context = cairo::Context::new(&surface);
+            let mut used_faces = vec![0];
+            let mut grid_glyphs: Vec<RenderedGlyph> = Vec::new();
 
-        context.set_source_rgba(0.0, 0.0, 0.0, 0.0);
-        context.set_operator(cairo::Operator::Source);
-        context.rectangle(0.0, 0.0, width as f64, height as f64);
-        context.fill();
+            // Images come first, so they take the place of font glyphs for their codepoints
+            let mut requests: Vec<Request> = (0..render_settings.image_glyphs.len()).map(Request::Image).collect();
+            requests.push(Request::NewRow);
+            requests.extend(render_settings.text.chars()
+                .map(|c| if c == '\n' { Request::NewRow } else { Request::Codepoint(c as usize) }));
+            // Glyphs of cells are found by codepoint, so grids leave glyphs requested by index out
+            if !render_settings.grid {
+                let glyph_count = variant.face.raw().num_glyphs as u32;
+                requests.extend(render_settings.glyph_indices.iter()
+                    .filter(|&&glyph_index| glyph_index > 0 && glyph_index < glyph_count)
+                    .map(|&glyph_index| Request::Glyph(glyph_index)));
+            }
+            if render_settings.shape_text && !render_settings.grid {
+                let shaped = Glyphs::shape(renderer, &variant.face, index, &render_settings.text, &render_settings.features);
+                for substitution in &shaped {
+                    requests.extend(substitution.glyphs.iter().map(|&g| Request::Glyph(g)));
+                }
+                for substitution in shaped {
+                    let known = substitutions.iter().any(|s: &SubstitutionInfo| {
+                        s.variant == index && s.codepoints == substitution.codepoints && s.before == substitution.before && s.after == substitution.after
+                    });
+                    if !known {
+                        substitutions.push(substitution);
+                    }
+                }
+            }
 
-        for character in render_settings.text.chars() {
-            if character == '\n' {
-                top += max_row_height + letter_spacing;
-                left = 0;
-                max_row_height = 0;
+            for request in requests {
+                let mut rendered_glyph = match request {
+                    Request::NewRow => {
+                        packer.new_row();
+                        continue
+                    },
+                    Request::Codepoint(codepoint) => {
+                        if info.iter().any(|g| g.variant == index && g.codepoint == Some(codepoint)) || grid_glyphs.iter().any(|g| g.codepoint == Some(codepoint)) {
+                            continue
+                        }
+
+                        // Codepoints mapped to the same glyph share its rect, grids have a cell for each
+                        if let Some((face, glyph_index)) = renderer.glyph_index(codepoint).filter(|_| !render_settings.grid) {
+                            let same_glyph = info.iter()
+                                .find(|g| g.variant == index && g.face == face && g.glyph_index == glyph_index)
+                                .cloned();
+                            if let Some(glyph) = same_glyph {
+                                info.push(GlyphInfo { codepoint: Some(codepoint), ..glyph });
+                                continue
+                            }
+                        }
+
+                        match renderer.render(codepoint) {
+                            Ok(rendered_glyph) => rendered_glyph,
+                            Err(ft::Error::InvalidCharacterCode) => {
+                                if !missing.contains(&codepoint) {
+                                    missing.push(codepoint);
+                                }
+                                continue
+                            },
+                            Err(err) => return Err(render_error(&format!("U+{:04X}", codepoint), err)),
+                        }
+                    },
+                    Request::Glyph(glyph_index) => {
+                        if info.iter().any(|g| g.variant == index && !g.image && g.face == 0 && g.glyph_index == glyph_index) {
+                            continue
+                        }
+                        renderer.render_glyph(0, glyph_index).map_err(|err| render_error(&format!("glyph {}", glyph_index), err))?
+                    },
+                    Request::Image(image) => {
+                        let image_glyph = &render_settings.image_glyphs[image];
+                        if info.iter().any(|g| g.variant == index && g.codepoint == Some(image_glyph.codepoint)) || grid_glyphs.iter().any(|g| g.codepoint == Some(image_glyph.codepoint)) {
+                            continue
+                        }
+                        image_glyph.render(renderer.cap_height())
+                    },
+                };
+                if !used_faces.contains(&rendered_glyph.face) {
+                    used_faces.push(rendered_glyph.face);
+                }
+                // Cells can only be sized once all glyphs and the line metrics are known
+                if render_settings.grid {
+                    grid_glyphs.push(rendered_glyph);
+                    continue
+                }
+                // Empty glyphs like space only advance the cursor and take no room in the atlas
+                if rendered_glyph.surface.get_width() == 0 || rendered_glyph.surface.get_height() == 0 {
+                    info.push(GlyphInfo {
+                        codepoint: rendered_glyph.codepoint,
+                        variant: index,
+                        face: rendered_glyph.face,
+                        glyph_index: rendered_glyph.glyph_index,
+                        image: rendered_glyph.image,
+                        codepoints: rendered_glyph.codepoints.clone(),
+                        page: 0,
+                        x: 0,
+                        y: 0,
+                        width: 0,
+                        height: 0,
+                        xoffset: 0,
+                        yoffset: 0,
+                        xadvance: rendered_glyph.advance.0,
+                        rotated: false,
+                    });
+                    continue
+                }
+
+                let glyph_right = rendered_glyph.surface.get_width() as u32 + 2 * letter_padding as u32;
+                let glyph_bottom = rendered_glyph.surface.get_height() as u32 + 2 * letter_padding as u32;
+
+                // Different glyphs with identical bitmaps share the rect of the first one
+                let data = rendered_glyph.surface.get_data().ok().map(|data| data.to_vec());
+                let packed = data.as_ref().and_then(|data| bitmaps.iter()
+                    .find(|b| b.width == glyph_right && b.height == glyph_bottom && &b.data == data)
+                    .map(|b| (b.page, b.left, b.top, b.rotated)));
+                let (page, left, top, rotated) = match packed {
+                    Some(position) => position,
+                    None => {
+                        let (page, left, top, rotated) = packer.pack_rotatable(glyph_right as i32, glyph_bottom as i32);
+
+                        while pages.len() <= page as usize {
+                            pages.push(Glyphs::create_page(width, height));
+                        }
+
+                        let context = cairo::Context::new(&pages[page as usize]);
+                        context.set_operator(cairo::Operator::Over);
+                        if rotated {
+                            // Clockwise, the bottom left corner of the glyph ends up at the top left
+                            context.translate((left + letter_padding + rendered_glyph.surface.get_height()) as f64, (top + letter_padding) as f64);
+                            context.rotate(f64::consts::FRAC_PI_2);
+                            context.set_source_surface(&rendered_glyph.surface, 0.0, 0.0);
+                        } else {
+                            context.set_source_surface(&rendered_glyph.surface, (left + letter_padding) as f64, (top + letter_padding) as f64);
+                        }
+                        context.paint();
+
+                        if let Some(data) = data {
+                            bitmaps.push(PackedBitmap {
+                                data: data,
+                                width: glyph_right,
+                                height: glyph_bottom,
+                                page: page,
+                                left: left,
+                                top: top,
+                                rotated: rotated,
+                            });
+                        }
+                        (page, left, top, rotated)
+                    },
+                };
+
+                info.push(GlyphInfo {
+                    codepoint: rendered_glyph.codepoint,
+                    variant: index,
+                    face: rendered_glyph.face,
+                    glyph_index: rendered_glyph.glyph_index,
+                    image: rendered_glyph.image,
+                    codepoints: rendered_glyph.codepoints.clone(),
+                    page: page,
+                    x: left as u32,
+                    y: top as u32,
+                    width: glyph_right,
+                    height: glyph_bottom,
+                    xoffset: rendered_glyph.offset.0,
+                    yoffset: rendered_glyph.offset.1,
+                    xadvance: rendered_glyph.advance.0,
+                    rotated: rotated,
+                });
+            }
+
+            metrics.push(Glyphs::line_metrics(renderer, &used_faces));
+            if render_settings.grid {
+                let placement = GridPlacement {
+                    glyphs: &grid_glyphs,
+                    variant: index,
+                    metrics: &metrics[index],
+                    page_width: width,
+                    page_height: height,
+                };
+                grids.push(Glyphs::place_in_grid(placement, render_settings, &mut pages, &mut info, &mut missing));
+            }
+
+            // Images have no kerning, even where the font kerns the codepoint they replace
+            let mut codepoints: Vec<usize> = info.iter()
+                .filter(|g| g.variant == index && !g.image)
+                .filter_map(|g| g.codepoint)
+                .collect();
+            codepoints.sort();
+            codepoints.dedup();
+            for &first in &codepoints {
+                for &second in &codepoints {
+                    let amount = renderer.kerning(first, second);
+                    if amount != 0 {
+                        kernings.push(KerningInfo {
+                            variant: index,
+                            first: first,
+                            second: second,
+                            amount: amount,
+                        });
+                    }
+                }
+            }
+        }
+
+        render_settings.face.set_char_size(0, render_settings.font_size*64, 0, 64).map_err(|err| render_error(&format!("{} px", render_settings.font_size), err))?;
+
+        // Extrusion needs straight alpha, cairo would drop the color of transparent pixels
+        let mut pages: Vec<Image> = pages.iter_mut().map(Image::from_surface).collect();
+        if render_settings.extrude != Extrude::None {
+            let padding = letter_padding as u32;
+            for (i, glyph) in info.iter().enumerate() {
+                if glyph.width == 0 || info[..i].iter().any(|g| (g.page, g.x, g.y) == (glyph.page, glyph.x, glyph.y)) {
+                    continue
+                }
+                let (x, y, width, height) = glyph.rect();
+                let inner = (x + padding, y + padding, width - 2 * padding, height - 2 * padding);
+                pages[glyph.page as usize].extrude((x, y, width, height), inner, render_settings.extrude);
+            }
+        }
+
+        Ok(Atlas {
+            glyphs: info,
+            pages: pages,
+            metrics: metrics,
+            kernings: kernings,
+            missing: missing,
+            grids: grids,
+            substitutions: substitutions,
+        })
+    }
+
+    /// Draws every glyph into the cell of its codepoint, with the text cursor at the left edge of
+    /// the cell and the top of the line at its top. Each variant starts on a fresh page and glyphs
+    /// reaching out of their cell get clipped. Codepoints before the first cell or beyond
+    /// `MAX_GRID_PAGES` are added to `missing`.
+    fn place_in_grid(placement: GridPlacement, render_settings: &RenderSettings, pages: &mut Vec<cairo::ImageSurface>, info: &mut Vec<GlyphInfo>, missing: &mut Vec<usize>) -> Grid {
+        let GridPlacement { glyphs: rendered_glyphs, variant, metrics, page_width: width, page_height: height } = placement;
+        let padding = render_settings.letter_padding as i32;
+        let max_advance = rendered_glyphs.iter().map(|g| g.advance.0).max().unwrap_or(0);
+        let cell_width = match render_settings.grid_cell_width {
+            0 => cmp::max(1, max_advance + 2 * padding),
+            cell_width => cell_width as i32,
+        };
+        let cell_height = match render_settings.grid_cell_height {
+            0 => cmp::max(1, metrics.height + 2 * padding),
+            cell_height => cell_height as i32,
+        };
+        let columns = cmp::max(1, cmp::min(render_settings.grid_columns as i32, width / cell_width));
+        let rows = cmp::max(1, height / cell_height);
+        let first_codepoint = render_settings.grid_first_codepoint as usize;
+        let first_page = if info.is_empty() { 0 } else { pages.len() as u32 };
+        let baseline = padding + metrics.ascender;
+        let mut cells = 0;
+
+        let cell_glyphs = rendered_glyphs.iter().filter_map(|g| g.codepoint.map(|codepoint| (codepoint, g)));
+        for (codepoint, glyph) in cell_glyphs {
+            let cell = codepoint as i64 - first_codepoint as i64;
+            if cell < 0 || cell >= (MAX_GRID_PAGES * columns * rows) as i64 {
+                if !missing.contains(&codepoint) {
+                    missing.push(codepoint);
+                }
                 continue
             }
+            let cell = cell as i32;
+            let page = first_page + (cell / columns / rows) as u32;
+            let left = cell % columns * cell_width;
+            let top = cell / columns % rows * cell_height;
+            cells = cmp::max(cells, cell + 1);
+
+            while pages.len() <= page as usize {
+                pages.push(Glyphs::create_page(width, height));
+            }
 
-            let rendered_glyph = renderer.render(character as usize).unwrap();
-            let glyph_right = rendered_glyph.surface.get_width() as u32 + 2 * letter_padding as u32;
-            let glyph_bottom = rendered_glyph.surface.get_height() as u32 + 2 * letter_padding as u32;
+            if glyph.surface.get_width() > 0 && glyph.surface.get_height() > 0 {
+                let context = cairo::Context::new(&pages[page as usize]);
+                context.rectangle(left as f64, top as f64, cell_width as f64, cell_height as f64);
+                context.clip();
+                context.set_source_surface(&glyph.surface, (left + padding + glyph.offset.0) as f64, (top + baseline - glyph.offset.1) as f64);
+                context.paint();
+            }
 
             info.push(GlyphInfo {
-                codepoint: rendered_glyph.codepoint,
+                codepoint: glyph.codepoint,
+                variant: variant,
+                face: glyph.face,
+                glyph_index: glyph.glyph_index,
+                image: glyph.image,
+                codepoints: glyph.codepoints.clone(),
+                page: page,
                 x: left as u32,
                 y: top as u32,
-                width: glyph_right,
-                height: glyph_bottom,
-                xoffset: rendered_glyph.offset.0,
-                yoffset: rendered_glyph.offset.1,
-                xadvance: rendered_glyph.advance.0 + 2 * letter_padding,
+                width: cell_width as u32,
+                height: cell_height as u32,
+                xoffset: 0,
+                yoffset: metrics.ascender,
+                xadvance: glyph.advance.0,
+                rotated: false,
             });
+        }
+
+        Grid {
+            first_codepoint: first_codepoint,
+            columns: columns as u32,
+            rows: rows as u32,
+            cell_width: cell_width as u32,
+            cell_height: cell_height as u32,
+            baseline: baseline,
+            first_page: first_page,
+            pages: ((cells + columns * rows - 1) / (columns * rows)) as u32,
+        }
+    }
+
+    /// Normalizes the line metrics of all faces that contributed glyphs. Glyph offsets are
+    /// relative to the shared baseline, so the line has to reach the highest ascender and the
+    /// lowest descender.
+    fn line_metrics(renderer: &Renderer, faces: &[usize]) -> LineMetrics {
+        let primary = renderer.size_metrics(0).expect("Need metrics");
+        let mut line_metrics = LineMetrics {
+            ascender: primary.ascender as i32 / 64,
+            descender: primary.descender as i32 / 64,
+            height: primary.height as i32 / 64,
+        };
 
-            context.set_operator(cairo::Operator::Over);
-            context.set_source_surface(&rendered_glyph.surface, (left + letter_padding) as f64, (top + letter_padding) as f64);
-            context.paint();
+        for metrics in faces.iter().filter_map(|&face| renderer.size_metrics(face)) {
+            line_metrics.ascender = cmp::max(line_metrics.ascender, metrics.ascender as i32 / 64);
+            line_metrics.descender = cmp::min(line_metrics.descender, metrics.descender as i32 / 64);
+        }
+
+        line_metrics.height = cmp::max(line_metrics.height, line_metrics.ascender - line_metrics.descender);
+        line_metrics
+    }
 
-            left += glyph_right as i32 + letter_spacing;
-            max_row_height = cmp::max(max_row_height, glyph_bottom as i32);
+    fn renderer_for<'a>(renderers: &'a mut Vec<(Rc<ft::Face<'static>>, Renderer)>, render_settings: &RenderSettings, variant: &FontVariant) -> &'a Renderer {
+        if let Some(position) = renderers.iter().position(|&(ref face, _)| Rc::ptr_eq(face, &variant.face)) {
+            return &renderers[position].1;
         }
 
-        (info, surface)
+        let mut renderer = Renderer::new(&render_settings.library, &variant.face);
+        renderer.set_color(&render_settings.font_color);
+        renderer.set_outline(&render_settings.border_color, render_settings.border_width);
+        renderer.set_fallback_faces(&render_settings.fallback_faces);
+        renderer.set_synthetic_style(render_settings.synthetic_bold, render_settings.synthetic_oblique, render_settings.stretch);
+        renderer.set_color_glyphs(render_settings.color_glyphs, render_settings.color_palette);
+        renderer.set_features(&render_settings.features);
+        renderers.push((variant.face.clone(), renderer));
+        &renderers.last().unwrap().1
+    }
+
+    /// Shapes the text with the primary face of a variant and keeps the clusters whose glyphs
+    /// differ from those the face maps their codepoints to. Clusters with characters the face
+    /// lacks are left to the fallback faces.
+    fn shape(renderer: &Renderer, face: &ft::Face, variant: usize, text: &str, features: &[Feature]) -> Vec<SubstitutionInfo> {
+        shaping::shape(face, text, features).into_iter().filter_map(|cluster| {
+            let codepoints: Vec<usize> = cluster.text.chars().map(|c| c as usize).collect();
+            let mapped: Vec<u32> = codepoints.iter().map(|&c| renderer.alternate(0, face.get_char_index(c))).collect();
+            if cluster.glyphs.contains(&0) || mapped.contains(&0) || cluster.glyphs == mapped {
+                return None
+            }
+            Some(SubstitutionInfo {
+                variant: variant,
+                codepoints: codepoints,
+                before: cluster.before.map(|c| c as usize),
+                after: cluster.after.map(|c| c as usize),
+                glyphs: cluster.glyphs,
+                positions: cluster.positions,
+            })
+        }).collect()
     }
-}
\ No newline at end of file
+
+    fn create_page(width: i32, height: i32) -> cairo::ImageSurface {
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).unwrap();
+        let context = cairo::Context::new(&surface);
+
+        context.set_source_rgba(0.0, 0.0, 0.0, 0.0);
+        context.set_operator(cairo::Operator::Source);
+        context.rectangle(0.0, 0.0, width as f64, height as f64);
+        context.fill();
+
+        surface
+    }
+
+    /// Path next to `path` with the file stem extended by `suffix`, e.g. `atlas_1.png`.
+    fn sibling_path(path: &Path, suffix: &str, extension: &str) -> PathBuf {
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        path.with_file_name(format!("{}_{}.{}", stem, suffix, extension))
+    }
+}
+
+fn render_error(what: &str, err: ft::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("Couldn't render {}: {}", what, err))
+}
@@ -0,0 +1,40 @@
+//! Module for writing a Godot 3 `BitmapFont` resource (`.tres`).
+//! For more info see the [resource format][1] and the [BitmapFont class][2].
+//!
+//! [1]: https://docs.godotengine.org/en/3.5/development/file_formats/tscn.html
+//! [2]: https://docs.godotengine.org/en/3.5/classes/class_bitmapfont.html
+//!
+
+use std::io;
+
+use font::{FontFile};
+
+pub fn write(font_file: &FontFile, write: &mut io::Write) -> io::Result<()> {
+    write!(write, "[gd_resource type=\"BitmapFont\" load_steps={} format=2]\n\n", font_file.pages.len() + 1)?;
+
+    // Godot resolves relative paths against the directory of the resource
+    for page in &font_file.pages {
+        write!(write, "[ext_resource path=\"{}\" type=\"Texture\" id={}]\n", page.file, page.id + 1)?;
+    }
+
+    let textures: Vec<String> = font_file.pages.iter().map(|p| format!("ExtResource( {} )", p.id + 1)).collect();
+    write!(write, "\n[resource]\n")?;
+    write!(write, "textures = [ {} ]\n", textures.join(", "))?;
+
+    // Each char is character, texture, rect x, y, width, height, align x, y and advance. The
+    // alignment is relative to the top of the line, the same as the BMFont offsets.
+    let chars: Vec<String> = font_file.chars.iter().map(|c| {
+        format!("{}, {}, {}, {}, {}, {}, {}, {}, {}", c.id, c.page, c.x, c.y, c.width, c.height, c.xoffset, c.yoffset, c.xadvance)
+    }).collect();
+    write!(write, "chars = PoolIntArray( {} )\n", chars.join(", "))?;
+
+    // Godot subtracts the kerning from the advance
+    let kernings: Vec<String> = font_file.kernings.iter().map(|k| format!("{}, {}, {}", k.first, k.second, -k.amount)).collect();
+    write!(write, "kernings = PoolIntArray( {} )\n", kernings.join(", "))?;
+
+    write!(write, "height = {}\n", font_file.common.line_height)?;
+    write!(write, "ascent = {}\n", font_file.common.base)?;
+    write!(write, "distance_field = false\n")?;
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Module for writing the cell layout of an atlas whose glyphs are placed in a fixed grid.
+//!
+//! Renderers that index glyphs by cell compute the cell of a codepoint from these parameters
+//! instead of looking up its rect in the descriptor:
+//!
+//! ```text
+//! {
+//!   "firstCodepoint": int, "columns": int, "rows": int, "cellWidth": int, "cellHeight": int,
+//!   "baseline": int, "firstPage": int, "pages": int
+//! }
+//! ```
+//!
+//! Cell `n = codepoint - firstCodepoint` is on page `firstPage + n / (columns * rows)`, in column
+//! `n % columns` and row `n / columns % rows`. `rows` is the number of rows on each page and
+//! `baseline` the distance from the top of a cell to the baseline of its glyph.
+//!
+
+use std::io;
+
+use json::{JsonWriter};
+
+/// Cell layout of the glyphs of one font variant.
+pub struct Grid {
+    pub first_codepoint: usize,
+    pub columns: u32,
+    /// Rows on each page
+    pub rows: u32,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    /// Distance from the top of a cell to the baseline
+    pub baseline: i32,
+    pub first_page: u32,
+    pub pages: u32,
+}
+
+pub fn write(grid: &Grid, write: &mut io::Write) -> io::Result<()> {
+    let mut out = JsonWriter::new(write);
+
+    out.begin_object()?;
+    out.key("firstCodepoint")?;
+    out.number(grid.first_codepoint as i64)?;
+    out.key("columns")?;
+    out.number(grid.columns as i64)?;
+    out.key("rows")?;
+    out.number(grid.rows as i64)?;
+    out.key("cellWidth")?;
+    out.number(grid.cell_width as i64)?;
+    out.key("cellHeight")?;
+    out.number(grid.cell_height as i64)?;
+    out.key("baseline")?;
+    out.number(grid.baseline as i64)?;
+    out.key("firstPage")?;
+    out.number(grid.first_page as i64)?;
+    out.key("pages")?;
+    out.number(grid.pages as i64)?;
+    out.end_object()?;
+    out.newline()
+}
@@ -0,0 +1,60 @@
+//! Module for writing the settings file of libGDX's Hiero tool, so the font can be regenerated
+//! or tweaked in Hiero. For more info see the [Hiero wiki page][1].
+//!
+//! [1]: https://libgdx.com/wiki/tools/hiero
+//!
+
+use gdk::{RGBA};
+use std::io;
+
+use font::{FontFile};
+use render_settings::{RenderSettings};
+
+/// Renders glyphs with FreeType, like this application does
+const RENDER_TYPE_FREETYPE: u32 = 2;
+
+pub fn write(font_file: &FontFile, render_settings: &RenderSettings, write: &mut io::Write) -> io::Result<()> {
+    let info = &font_file.info;
+
+    // Hiero looks up the font by name, the path of the font file isn't known here
+    write!(write, "font.name={}\n", info.face)?;
+    write!(write, "font.size={}\n", info.size)?;
+    write!(write, "bold={}\n", info.bold)?;
+    write!(write, "italic={}\n", info.italic)?;
+    write!(write, "gamma=1.8\n")?;
+    write!(write, "mono=false\n\n")?;
+
+    write!(write, "font2.file=\n")?;
+    write!(write, "font2.use=false\n\n")?;
+
+    // Hiero keeps the padding out of the advances with negative advance adjustments
+    write!(write, "pad.top={}\n", info.padding[0])?;
+    write!(write, "pad.right={}\n", info.padding[1])?;
+    write!(write, "pad.bottom={}\n", info.padding[2])?;
+    write!(write, "pad.left={}\n", info.padding[3])?;
+    write!(write, "pad.advance.x={}\n", -((info.padding[1] + info.padding[3]) as i32))?;
+    write!(write, "pad.advance.y={}\n\n", -((info.padding[0] + info.padding[2]) as i32))?;
+
+    write!(write, "glyph.native.rendering=false\n")?;
+    write!(write, "glyph.page.width={}\n", font_file.common.scale_w)?;
+    write!(write, "glyph.page.height={}\n", font_file.common.scale_h)?;
+    write!(write, "glyph.text={}\n\n", render_settings.text.replace('\n', "\\n"))?;
+
+    write!(write, "render_type={}\n\n", RENDER_TYPE_FREETYPE)?;
+
+    write!(write, "effect.class=com.badlogic.gdx.tools.hiero.unicodefont.effects.ColorEffect\n")?;
+    write!(write, "effect.Color={}\n\n", hex_color(&render_settings.font_color))?;
+
+    if render_settings.border_width > 0 {
+        write!(write, "effect.class=com.badlogic.gdx.tools.hiero.unicodefont.effects.OutlineEffect\n")?;
+        write!(write, "effect.Width={:.1}\n", render_settings.border_width as f64)?;
+        write!(write, "effect.Color={}\n", hex_color(&render_settings.border_color))?;
+        write!(write, "effect.Join=ROUND\n\n")?;
+    }
+
+    Ok(())
+}
+
+fn hex_color(color: &RGBA) -> String {
+    format!("{:02x}{:02x}{:02x}", (color.red * 255.0).round() as u8, (color.green * 255.0).round() as u8, (color.blue * 255.0).round() as u8)
+}
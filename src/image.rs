@@ -0,0 +1,728 @@
+//! Module for atlas pages with straight alpha and writing them as PNG or in formats which can be
+//! uploaded to the GPU without decoding. For more info see the [TGA][1], [DDS][2] and [KTX2][3]
+//! specifications.
+//!
+//! [1]: http://www.dca.fee.unicamp.br/~martino/disciplinas/ea978/tgaffs.pdf
+//! [2]: https://docs.microsoft.com/en-us/windows/win32/direct3ddds/dx-graphics-dds-pguide
+//! [3]: https://registry.khronos.org/KTX/specs/2.0/ktxspec.v2.html
+//!
+
+use cairo;
+use gdk_pixbuf::{Colorspace, Pixbuf};
+use glib::translate::{ToGlibPtr};
+use std::cmp;
+use std::ffi::{CString};
+use std::io;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use std::slice;
+
+extern "C" {
+    fn gdk_pixbuf_save_to_callbackv(pixbuf: *mut c_void, save_func: unsafe extern "C" fn(*const u8, usize, *mut *mut c_void, *mut c_void) -> i32,
+                                    user_data: *mut c_void, type_: *const c_char, option_keys: *mut *mut c_char, option_values: *mut *mut c_char,
+                                    error: *mut *mut c_void) -> i32;
+    fn g_error_free(error: *mut c_void);
+}
+
+const COLORSPACE_RGB: Colorspace = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImageFormat {
+    Png,
+    /// Uncompressed TGA, top-left origin
+    Tga,
+    Dds,
+    Ktx2,
+    /// Pixels only, rows tightly packed without a header
+    Raw,
+}
+
+impl ImageFormat {
+    pub fn all() -> &'static [ImageFormat] {
+        static ALL: [ImageFormat; 5] = [ImageFormat::Png, ImageFormat::Tga, ImageFormat::Dds, ImageFormat::Ktx2, ImageFormat::Raw];
+        &ALL
+    }
+
+    /// Human readable name, used in the exporter settings
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ImageFormat::Png => "PNG",
+            ImageFormat::Tga => "TGA",
+            ImageFormat::Dds => "DDS",
+            ImageFormat::Ktx2 => "KTX2",
+            ImageFormat::Raw => "Raw",
+        }
+    }
+
+    /// Short identifier, used on the command line
+    pub fn id(&self) -> &'static str {
+        match *self {
+            ImageFormat::Png => "png",
+            ImageFormat::Tga => "tga",
+            ImageFormat::Dds => "dds",
+            ImageFormat::Ktx2 => "ktx2",
+            ImageFormat::Raw => "raw",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.id()
+    }
+
+    pub fn from_id(id: &str) -> Option<ImageFormat> {
+        ImageFormat::all().iter().cloned().find(|f| f.id() == id)
+    }
+
+    pub fn supports_mipmaps(&self) -> bool {
+        match *self {
+            ImageFormat::Dds | ImageFormat::Ktx2 => true,
+            _ => false,
+        }
+    }
+}
+
+/// How glyph pixels are copied into the transparent pixels around them, so bilinear filtering at
+/// the glyph borders doesn't blend in black.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Extrude {
+    None,
+    /// Spreads the colors into all transparent pixels of the glyph rect, keeping them transparent
+    Color,
+    /// Repeats the outermost pixels of the glyph bitmap, including alpha, into the padding
+    ColorAndAlpha,
+}
+
+impl Extrude {
+    pub fn all() -> &'static [Extrude] {
+        static ALL: [Extrude; 3] = [Extrude::None, Extrude::Color, Extrude::ColorAndAlpha];
+        &ALL
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Extrude::None => "None",
+            Extrude::Color => "Color",
+            Extrude::ColorAndAlpha => "Color and Alpha",
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        match *self {
+            Extrude::None => "none",
+            Extrude::Color => "color",
+            Extrude::ColorAndAlpha => "alpha",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Extrude> {
+        Extrude::all().iter().cloned().find(|e| e.id() == id)
+    }
+}
+
+/// Channels stored by the image formats except PNG, which always stores RGBA.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PixelFormat {
+    /// Red, green, blue and straight alpha, 8 bits each
+    Rgba8,
+    /// Coverage as an alpha channel
+    A8,
+    /// Coverage as a luminance channel
+    L8,
+}
+
+impl PixelFormat {
+    pub fn all() -> &'static [PixelFormat] {
+        static ALL: [PixelFormat; 3] = [PixelFormat::Rgba8, PixelFormat::A8, PixelFormat::L8];
+        &ALL
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PixelFormat::Rgba8 => "RGBA8",
+            PixelFormat::A8 => "A8",
+            PixelFormat::L8 => "L8",
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        match *self {
+            PixelFormat::Rgba8 => "rgba8",
+            PixelFormat::A8 => "a8",
+            PixelFormat::L8 => "l8",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<PixelFormat> {
+        PixelFormat::all().iter().cloned().find(|f| f.id() == id)
+    }
+
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match *self {
+            PixelFormat::Rgba8 => 4,
+            PixelFormat::A8 | PixelFormat::L8 => 1,
+        }
+    }
+}
+
+/// Alpha above which a pixel counts as covered when preserving coverage in mip levels, the usual
+/// alpha test reference
+const ALPHA_TEST_THRESHOLD: f64 = 0.5;
+
+/// Atlas page with straight, i.e. not premultiplied, RGBA pixels.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn from_surface(surface: &mut cairo::ImageSurface) -> Image {
+        let width = surface.get_width() as u32;
+        let height = surface.get_height() as u32;
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().expect("Page is still in use");
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                // cairo's native endian ARGB32 with premultiplied alpha
+                let offset = y * stride + x * 4;
+                let alpha = data[offset + 3];
+                let unpremultiply = |c: u8| if alpha == 0 { 0 } else { ((c as u32 * 255 + alpha as u32 / 2) / alpha as u32) as u8 };
+                pixels.push(unpremultiply(data[offset + 2]));
+                pixels.push(unpremultiply(data[offset + 1]));
+                pixels.push(unpremultiply(data[offset + 0]));
+                pixels.push(alpha);
+            }
+        }
+
+        Image {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+
+    /// Premultiplies the pixels into a surface for drawing with cairo.
+    pub fn to_surface(&self) -> cairo::ImageSurface {
+        let mut surface = cairo::ImageSurface::create(cairo::Format::ARgb32, self.width as i32, self.height as i32).unwrap();
+        let stride = surface.get_stride() as usize;
+        {
+            let mut data = surface.get_data().unwrap();
+            for (index, pixel) in self.pixels.chunks(4).enumerate() {
+                let offset = (index / self.width as usize) * stride + (index % self.width as usize) * 4;
+                let premultiply = |c: u8| ((c as u32 * pixel[3] as u32 + 127) / 255) as u8;
+                data[offset + 0] = premultiply(pixel[2]);
+                data[offset + 1] = premultiply(pixel[1]);
+                data[offset + 2] = premultiply(pixel[0]);
+                data[offset + 3] = pixel[3];
+            }
+        }
+        surface
+    }
+
+    /// Extrudes the glyph bitmap at `inner` into the transparent pixels of `rect` around it, both
+    /// given as x, y, width and height.
+    pub fn extrude(&mut self, rect: (u32, u32, u32, u32), inner: (u32, u32, u32, u32), mode: Extrude) {
+        let (left, top, width, height) = self.clip(rect);
+        let (inner_left, inner_top, inner_width, inner_height) = self.clip(inner);
+        if width == 0 || height == 0 || inner_width == 0 || inner_height == 0 {
+            return;
+        }
+
+        match mode {
+            Extrude::None => (),
+            Extrude::Color => {
+                // Grows the colored area one pixel per pass, averaging the colored neighbours
+                let mut colored: Vec<bool> = (0..width * height).map(|i| self.pixels[self.offset(left + i % width, top + i / width) + 3] > 0).collect();
+                loop {
+                    let mut grown = Vec::new();
+                    for y in 0..height {
+                        for x in 0..width {
+                            if colored[(y * width + x) as usize] {
+                                continue;
+                            }
+                            let mut sum = [0u32; 3];
+                            let mut count = 0;
+                            for &(dx, dy) in &[(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                                if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 || !colored[(ny as u32 * width + nx as u32) as usize] {
+                                    continue;
+                                }
+                                let offset = self.offset(left + nx as u32, top + ny as u32);
+                                for channel in 0..3 {
+                                    sum[channel] += self.pixels[offset + channel] as u32;
+                                }
+                                count += 1;
+                            }
+                            if count > 0 {
+                                grown.push((x, y, [(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8]));
+                            }
+                        }
+                    }
+                    if grown.is_empty() {
+                        break;
+                    }
+                    for (x, y, color) in grown {
+                        let offset = self.offset(left + x, top + y);
+                        self.pixels[offset..offset + 3].copy_from_slice(&color);
+                        colored[(y * width + x) as usize] = true;
+                    }
+                }
+            },
+            Extrude::ColorAndAlpha => {
+                for y in top..top + height {
+                    for x in left..left + width {
+                        let inside_x = cmp::min(cmp::max(x, inner_left), inner_left + inner_width - 1);
+                        let inside_y = cmp::min(cmp::max(y, inner_top), inner_top + inner_height - 1);
+                        if (inside_x, inside_y) != (x, y) {
+                            let source = self.offset(inside_x, inside_y);
+                            let target = self.offset(x, y);
+                            let pixel = [self.pixels[source], self.pixels[source + 1], self.pixels[source + 2], self.pixels[source + 3]];
+                            self.pixels[target..target + 4].copy_from_slice(&pixel);
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    fn clip(&self, rect: (u32, u32, u32, u32)) -> (u32, u32, u32, u32) {
+        let left = cmp::min(rect.0, self.width);
+        let top = cmp::min(rect.1, self.height);
+        (left, top, cmp::min(rect.2, self.width - left), cmp::min(rect.3, self.height - top))
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        ((y * self.width + x) * 4) as usize
+    }
+
+    /// Pixels converted to the pixel format, rows tightly packed.
+    pub fn data(&self, pixel_format: PixelFormat) -> Vec<u8> {
+        match pixel_format {
+            PixelFormat::Rgba8 => self.pixels.clone(),
+            PixelFormat::A8 | PixelFormat::L8 => self.pixels.chunks(4).map(|p| p[3]).collect(),
+        }
+    }
+
+    /// Writes the image in the given format, PNG always as RGBA and the others with the channels
+    /// of `pixel_format`, with the full mip chain if `mipmaps` is set and the format supports it.
+    pub fn write(&self, format: ImageFormat, pixel_format: PixelFormat, mipmaps: bool, write: &mut io::Write) -> io::Result<()> {
+        let mut levels = Vec::new();
+        if mipmaps && format.supports_mipmaps() {
+            levels = self.mip_chain();
+        }
+        let levels: Vec<&Image> = Some(self).into_iter().chain(levels.iter()).collect();
+
+        match format {
+            ImageFormat::Png => self.write_png(write),
+            ImageFormat::Tga => self.write_tga(pixel_format, write),
+            ImageFormat::Dds => Image::write_dds(&levels, pixel_format, write),
+            ImageFormat::Ktx2 => Image::write_ktx2(&levels, pixel_format, write),
+            ImageFormat::Raw => write.write_all(&self.data(pixel_format)),
+        }
+    }
+
+    /// Levels below this one down to 1x1. Each level halves the previous one with a box filter,
+    /// then scales the alpha so the same share of pixels passes the alpha test as in this level,
+    /// which keeps glyphs from thinning out in the distance.
+    pub fn mip_chain(&self) -> Vec<Image> {
+        let coverage = self.coverage(1.0);
+        let mut levels: Vec<Image> = Vec::new();
+
+        while levels.last().unwrap_or(self).width > 1 || levels.last().unwrap_or(self).height > 1 {
+            let mut level = levels.last().unwrap_or(self).downsample();
+            level.scale_alpha_to_coverage(coverage);
+            levels.push(level);
+        }
+
+        levels
+    }
+
+    fn downsample(&self) -> Image {
+        let width = cmp::max(1, self.width / 2);
+        let height = cmp::max(1, self.height / 2);
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                // Colors are weighted by alpha, so transparent pixels don't darken the edges
+                let mut sum = [0u32; 4];
+                for &(sx, sy) in &[(2 * x, 2 * y), (2 * x + 1, 2 * y), (2 * x, 2 * y + 1), (2 * x + 1, 2 * y + 1)] {
+                    let offset = ((cmp::min(sy, self.height - 1) * self.width + cmp::min(sx, self.width - 1)) * 4) as usize;
+                    let alpha = self.pixels[offset + 3] as u32;
+                    for channel in 0..3 {
+                        sum[channel] += self.pixels[offset + channel] as u32 * alpha;
+                    }
+                    sum[3] += alpha;
+                }
+                for channel in 0..3 {
+                    pixels.push(if sum[3] == 0 { 0 } else { ((sum[channel] + sum[3] / 2) / sum[3]) as u8 });
+                }
+                pixels.push(((sum[3] + 2) / 4) as u8);
+            }
+        }
+
+        Image {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+
+    /// Share of pixels whose alpha multiplied by `scale` passes the alpha test.
+    fn coverage(&self, scale: f64) -> f64 {
+        let covered = self.pixels.chunks(4).filter(|p| p[3] as f64 / 255.0 * scale > ALPHA_TEST_THRESHOLD).count();
+        covered as f64 / (self.width * self.height) as f64
+    }
+
+    fn scale_alpha_to_coverage(&mut self, coverage: f64) {
+        if coverage == 0.0 {
+            return;
+        }
+
+        let (mut low, mut high) = (0.0, 4.0);
+        for _ in 0..16 {
+            let scale = (low + high) / 2.0;
+            if self.coverage(scale) < coverage {
+                low = scale;
+            } else {
+                high = scale;
+            }
+        }
+
+        for pixel in self.pixels.chunks_mut(4) {
+            pixel[3] = (pixel[3] as f64 * high).round().min(255.0) as u8;
+        }
+    }
+
+    /// The gdk-pixbuf bindings have no functions for saving, so the PNG encoder is called through
+    /// `gdk_pixbuf_save_to_callbackv`, which hands the encoded chunks to any writer.
+    fn write_png(&self, write: &mut io::Write) -> io::Result<()> {
+        /// Writer of the encoded chunks and the first error it returned
+        struct Output<'a> {
+            write: &'a mut io::Write,
+            error: Option<io::Error>,
+        }
+
+        unsafe extern "C" fn write_chunk(buffer: *const u8, count: usize, _: *mut *mut c_void, data: *mut c_void) -> i32 {
+            let output = &mut *(data as *mut Output);
+            match output.write.write_all(slice::from_raw_parts(buffer, count)) {
+                Ok(()) => 1,
+                Err(err) => {
+                    output.error = Some(err);
+                    0
+                },
+            }
+        }
+
+        // gdk-pixbuf keeps alpha straight, unlike cairo which would lose the colors of transparent
+        // pixels written by extrusion
+        let pixbuf = Pixbuf::new_from_vec(self.pixels.clone(), COLORSPACE_RGB, true, 8, self.width as i32, self.height as i32, self.width as i32 * 4);
+        let png = CString::new("png").unwrap();
+        let mut error: *mut c_void = ptr::null_mut();
+        let mut output = Output {
+            write: write,
+            error: None,
+        };
+        let saved = unsafe {
+            let raw_pixbuf: *const _ = pixbuf.to_glib_none().0;
+            gdk_pixbuf_save_to_callbackv(raw_pixbuf as *mut c_void, write_chunk, &mut output as *mut Output as *mut c_void,
+                                         png.as_ptr(), ptr::null_mut(), ptr::null_mut(), &mut error)
+        };
+
+        if !error.is_null() {
+            unsafe { g_error_free(error) };
+        }
+        match output.error {
+            Some(err) => Err(err),
+            None if saved == 0 => Err(io::Error::new(io::ErrorKind::Other, "Couldn't encode PNG")),
+            None => Ok(()),
+        }
+    }
+
+    fn write_tga(&self, pixel_format: PixelFormat, write: &mut io::Write) -> io::Result<()> {
+        if self.width > 0xFFFF || self.height > 0xFFFF {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "TGA images are at most 65535 pixels wide and high"));
+        }
+
+        const TRUE_COLOR: u8 = 2;
+        const GRAYSCALE: u8 = 3;
+        const TOP_LEFT_ORIGIN: u8 = 0x20;
+
+        let (image_type, bits, alpha_bits) = match pixel_format {
+            PixelFormat::Rgba8 => (TRUE_COLOR, 32, 8),
+            PixelFormat::A8 => (GRAYSCALE, 8, 8),
+            PixelFormat::L8 => (GRAYSCALE, 8, 0),
+        };
+
+        let mut header = vec![0u8, 0, image_type, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        push_u16(&mut header, self.width as u16);
+        push_u16(&mut header, self.height as u16);
+        header.push(bits);
+        header.push(TOP_LEFT_ORIGIN | alpha_bits);
+        write.write_all(&header)?;
+
+        let mut data = self.data(pixel_format);
+        if pixel_format == PixelFormat::Rgba8 {
+            // TGA stores BGRA
+            for pixel in data.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        write.write_all(&data)
+    }
+
+    /// Writes the levels largest first, the first one being the image.
+    fn write_dds(levels: &[&Image], pixel_format: PixelFormat, write: &mut io::Write) -> io::Result<()> {
+        const DDSD_CAPS: u32 = 0x1;
+        const DDSD_HEIGHT: u32 = 0x2;
+        const DDSD_WIDTH: u32 = 0x4;
+        const DDSD_PITCH: u32 = 0x8;
+        const DDSD_PIXELFORMAT: u32 = 0x1000;
+        const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+        const DDPF_ALPHAPIXELS: u32 = 0x1;
+        const DDPF_ALPHA: u32 = 0x2;
+        const DDPF_RGB: u32 = 0x40;
+        const DDPF_LUMINANCE: u32 = 0x20000;
+        const DDSCAPS_COMPLEX: u32 = 0x8;
+        const DDSCAPS_TEXTURE: u32 = 0x1000;
+        const DDSCAPS_MIPMAP: u32 = 0x400000;
+
+        // Flags, bit count and red, green, blue and alpha masks
+        let (flags, bits, masks) = match pixel_format {
+            PixelFormat::Rgba8 => (DDPF_RGB | DDPF_ALPHAPIXELS, 32, [0xFF, 0xFF00, 0xFF0000, 0xFF000000]),
+            PixelFormat::A8 => (DDPF_ALPHA, 8, [0, 0, 0, 0xFF]),
+            PixelFormat::L8 => (DDPF_LUMINANCE, 8, [0xFF, 0, 0, 0]),
+        };
+
+        let image = levels[0];
+        let mipmaps = levels.len() > 1;
+
+        let mut header = Vec::with_capacity(128);
+        header.extend_from_slice(b"DDS ");
+        push_u32(&mut header, 124);
+        push_u32(&mut header, DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PITCH | DDSD_PIXELFORMAT | if mipmaps { DDSD_MIPMAPCOUNT } else { 0 });
+        push_u32(&mut header, image.height);
+        push_u32(&mut header, image.width);
+        push_u32(&mut header, image.width * pixel_format.bytes_per_pixel());
+        push_u32(&mut header, 0);
+        push_u32(&mut header, if mipmaps { levels.len() as u32 } else { 0 });
+        for _ in 0..11 {
+            push_u32(&mut header, 0);
+        }
+
+        push_u32(&mut header, 32);
+        push_u32(&mut header, flags);
+        push_u32(&mut header, 0);
+        push_u32(&mut header, bits);
+        for &mask in &masks {
+            push_u32(&mut header, mask);
+        }
+
+        push_u32(&mut header, DDSCAPS_TEXTURE | if mipmaps { DDSCAPS_COMPLEX | DDSCAPS_MIPMAP } else { 0 });
+        for _ in 0..4 {
+            push_u32(&mut header, 0);
+        }
+        write.write_all(&header)?;
+
+        for level in levels {
+            write.write_all(&level.data(pixel_format))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the levels, the first one being the image. The level index lists them largest first
+    /// while the data is stored smallest first.
+    fn write_ktx2(levels: &[&Image], pixel_format: PixelFormat, write: &mut io::Write) -> io::Result<()> {
+        const VK_FORMAT_R8_UNORM: u32 = 9;
+        const VK_FORMAT_R8G8B8A8_UNORM: u32 = 37;
+        const KHR_DF_MODEL_RGBSDA: u32 = 1;
+        const KHR_DF_PRIMARIES_BT709: u32 = 1;
+        const KHR_DF_TRANSFER_LINEAR: u32 = 1;
+        const KHR_DF_CHANNEL_RED: u32 = 0;
+        const KHR_DF_CHANNEL_GREEN: u32 = 1;
+        const KHR_DF_CHANNEL_BLUE: u32 = 2;
+        const KHR_DF_CHANNEL_ALPHA: u32 = 15;
+        const HEADER_SIZE: u32 = 80;
+        const LEVEL_INDEX_SIZE: u32 = 24;
+
+        let (vk_format, channels, swizzle) = match pixel_format {
+            PixelFormat::Rgba8 => (VK_FORMAT_R8G8B8A8_UNORM, vec![KHR_DF_CHANNEL_RED, KHR_DF_CHANNEL_GREEN, KHR_DF_CHANNEL_BLUE, KHR_DF_CHANNEL_ALPHA], None),
+            PixelFormat::A8 => (VK_FORMAT_R8_UNORM, vec![KHR_DF_CHANNEL_RED], Some("000r")),
+            PixelFormat::L8 => (VK_FORMAT_R8_UNORM, vec![KHR_DF_CHANNEL_RED], Some("rrr1")),
+        };
+        let image = levels[0];
+        let data: Vec<Vec<u8>> = levels.iter().map(|l| l.data(pixel_format)).collect();
+
+        // Data format descriptor with a single basic block
+        let mut dfd = Vec::new();
+        let block_size = 24 + 16 * channels.len() as u32;
+        push_u32(&mut dfd, 4 + block_size);
+        push_u32(&mut dfd, 0);
+        push_u32(&mut dfd, 2 | block_size << 16);
+        push_u32(&mut dfd, KHR_DF_MODEL_RGBSDA | KHR_DF_PRIMARIES_BT709 << 8 | KHR_DF_TRANSFER_LINEAR << 16);
+        push_u32(&mut dfd, 0);
+        push_u32(&mut dfd, pixel_format.bytes_per_pixel());
+        push_u32(&mut dfd, 0);
+        for (index, &channel) in channels.iter().enumerate() {
+            push_u32(&mut dfd, (index as u32 * 8) | 7 << 16 | channel << 24);
+            push_u32(&mut dfd, 0);
+            push_u32(&mut dfd, 0);
+            push_u32(&mut dfd, 255);
+        }
+
+        // Key/value data, sorted by key
+        let mut kvd = Vec::new();
+        if let Some(swizzle) = swizzle {
+            push_key_value(&mut kvd, "KTXswizzle", swizzle);
+        }
+        push_key_value(&mut kvd, "KTXwriter", "font-atlas");
+
+        let dfd_offset = HEADER_SIZE + LEVEL_INDEX_SIZE * levels.len() as u32;
+        let kvd_offset = dfd_offset + dfd.len() as u32;
+
+        // Levels are aligned to the least common multiple of the texel size and 4
+        let mut level_offsets = vec![0; levels.len()];
+        let mut offset = kvd_offset + kvd.len() as u32;
+        for (index, level) in data.iter().enumerate().rev() {
+            offset = align(offset, 4);
+            level_offsets[index] = offset;
+            offset += level.len() as u32;
+        }
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&[0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A]);
+        push_u32(&mut header, vk_format);
+        push_u32(&mut header, 1);
+        push_u32(&mut header, image.width);
+        push_u32(&mut header, image.height);
+        push_u32(&mut header, 0);
+        push_u32(&mut header, 0);
+        push_u32(&mut header, 1);
+        push_u32(&mut header, levels.len() as u32);
+        push_u32(&mut header, 0);
+
+        push_u32(&mut header, dfd_offset);
+        push_u32(&mut header, dfd.len() as u32);
+        push_u32(&mut header, kvd_offset);
+        push_u32(&mut header, kvd.len() as u32);
+        push_u64(&mut header, 0);
+        push_u64(&mut header, 0);
+
+        for (&level_offset, level) in level_offsets.iter().zip(&data) {
+            push_u64(&mut header, level_offset as u64);
+            push_u64(&mut header, level.len() as u64);
+            push_u64(&mut header, level.len() as u64);
+        }
+
+        header.extend_from_slice(&dfd);
+        header.extend_from_slice(&kvd);
+        for (&level_offset, level) in level_offsets.iter().zip(&data).rev() {
+            header.resize(level_offset as usize, 0);
+            header.extend_from_slice(level);
+        }
+        write.write_all(&header)
+    }
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+}
+
+fn push_u64(out: &mut Vec<u8>, value: u64) {
+    push_u32(out, value as u32);
+    push_u32(out, (value >> 32) as u32);
+}
+
+/// Appends a KTX2 key/value entry, both NUL terminated and padded to 4 bytes.
+fn push_key_value(out: &mut Vec<u8>, key: &str, value: &str) {
+    push_u32(out, (key.len() + value.len() + 2) as u32);
+    out.extend_from_slice(key.as_bytes());
+    out.push(0);
+    out.extend_from_slice(value.as_bytes());
+    out.push(0);
+    let padded = align(out.len() as u32, 4) as usize;
+    out.resize(padded, 0);
+}
+
+fn align(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) / alignment * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Image {
+        Image {
+            width: 3,
+            height: 2,
+            pixels: (0..24).collect(),
+        }
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        data[offset] as u32 | (data[offset + 1] as u32) << 8 | (data[offset + 2] as u32) << 16 | (data[offset + 3] as u32) << 24
+    }
+
+    #[test]
+    fn writes_tga_header() {
+        let mut tga = Vec::new();
+        image().write_tga(PixelFormat::Rgba8, &mut tga).unwrap();
+        assert_eq!(&tga[..18], &[0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 2, 0, 32, 0x28]);
+        assert_eq!(tga.len(), 18 + 24);
+        // BGRA
+        assert_eq!(&tga[18..22], &[2, 1, 0, 3]);
+
+        let mut tga = Vec::new();
+        image().write_tga(PixelFormat::L8, &mut tga).unwrap();
+        assert_eq!(&tga[..18], &[0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 2, 0, 8, 0x20]);
+        assert_eq!(&tga[18..], &[3, 7, 11, 15, 19, 23]);
+    }
+
+    #[test]
+    fn writes_dds_header() {
+        let mut dds = Vec::new();
+        Image::write_dds(&[&image()], PixelFormat::A8, &mut dds).unwrap();
+        assert_eq!(&dds[..4], b"DDS ");
+        assert_eq!(dds.len(), 128 + 6);
+        assert_eq!(u32_at(&dds, 4), 124);
+        assert_eq!(u32_at(&dds, 8), 0x100F);
+        assert_eq!(u32_at(&dds, 12), 2);
+        assert_eq!(u32_at(&dds, 16), 3);
+        assert_eq!(u32_at(&dds, 20), 3);
+        assert_eq!(u32_at(&dds, 28), 0);
+        // Pixel format: size, flags, bit count and alpha mask
+        assert_eq!(u32_at(&dds, 76), 32);
+        assert_eq!(u32_at(&dds, 80), 0x2);
+        assert_eq!(u32_at(&dds, 88), 8);
+        assert_eq!(u32_at(&dds, 104), 0xFF);
+        assert_eq!(u32_at(&dds, 108), 0x1000);
+    }
+
+    #[test]
+    fn writes_ktx2_header() {
+        let mut ktx2 = Vec::new();
+        Image::write_ktx2(&[&image()], PixelFormat::Rgba8, &mut ktx2).unwrap();
+        assert_eq!(&ktx2[..12], &[0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A]);
+        assert_eq!(u32_at(&ktx2, 12), 37);
+        assert_eq!(u32_at(&ktx2, 16), 1);
+        assert_eq!(u32_at(&ktx2, 20), 3);
+        assert_eq!(u32_at(&ktx2, 24), 2);
+        assert_eq!(u32_at(&ktx2, 36), 1);
+        assert_eq!(u32_at(&ktx2, 40), 1);
+
+        // The descriptor follows the single level index entry and the data ends the file
+        assert_eq!(u32_at(&ktx2, 48), 80 + 24);
+        let level_offset = u32_at(&ktx2, 80) as usize;
+        assert_eq!(level_offset % 4, 0);
+        assert_eq!(u32_at(&ktx2, 88), 24);
+        assert_eq!(ktx2.len(), level_offset + 24);
+        assert_eq!(&ktx2[level_offset..], &image().pixels[..]);
+    }
+}
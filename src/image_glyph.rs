@@ -0,0 +1,87 @@
+//! Images like button prompts or currency symbols packed into the atlas as the glyph of a
+//! codepoint, usually one of the private use area.
+
+use cairo::{self, Context, Format, ImageSurface};
+use std::cmp;
+use std::fs::{File};
+use std::io;
+use std::path::{Path};
+
+use glyph::{RenderedGlyph};
+
+/// Placement of an image relative to the text cursor on the baseline, in pixels.
+#[derive(Clone, Copy)]
+pub struct ImageMetrics {
+    /// Left edge of the image right of the text cursor
+    pub xoffset: i32,
+    /// Top edge of the image above the baseline
+    pub yoffset: i32,
+    /// Advance of the text cursor
+    pub xadvance: i32,
+}
+
+#[derive(Clone)]
+pub struct ImageGlyph {
+    pub codepoint: usize,
+    pub surface: ImageSurface,
+    /// The image keeps its size and is placed with these metrics, or is scaled to the cap height
+    /// and put on the baseline if `None`
+    pub metrics: Option<ImageMetrics>,
+}
+
+impl ImageGlyph {
+    /// Reads a PNG file, converted to the premultiplied ARGB of rendered glyphs.
+    pub fn load<P: AsRef<Path>>(codepoint: usize, path: P, metrics: Option<ImageMetrics>) -> io::Result<ImageGlyph> {
+        let mut file = File::open(path)?;
+        let png = ImageSurface::create_from_png(&mut file).map_err(|err| match err {
+            cairo::IoError::Io(err) => err,
+            cairo::IoError::Cairo(status) => io::Error::new(io::ErrorKind::InvalidData, format!("Couldn't read PNG: {:?}", status)),
+        })?;
+
+        let surface = ImageSurface::create(Format::ARgb32, png.get_width(), png.get_height())
+            .map_err(|status| io::Error::new(io::ErrorKind::Other, format!("Couldn't create surface: {:?}", status)))?;
+        {
+            let context = Context::new(&surface);
+            context.set_source_surface(&png, 0.0, 0.0);
+            context.paint();
+        }
+
+        Ok(ImageGlyph {
+            codepoint: codepoint,
+            surface: surface,
+            metrics: metrics,
+        })
+    }
+
+    /// Turns the image into a glyph for a font with the given cap height in pixels.
+    pub fn render(&self, cap_height: i32) -> RenderedGlyph {
+        let (surface, offset, advance) = match self.metrics {
+            Some(metrics) => (self.surface.clone(), (metrics.xoffset, metrics.yoffset), metrics.xadvance),
+            None => {
+                let height = cmp::max(1, cap_height);
+                let scale = height as f64 / cmp::max(1, self.surface.get_height()) as f64;
+                let width = cmp::max(1, (self.surface.get_width() as f64 * scale).round() as i32);
+
+                let scaled = ImageSurface::create(Format::ARgb32, width, height).expect("Couldn't create surface");
+                {
+                    let context = Context::new(&scaled);
+                    context.scale(width as f64 / cmp::max(1, self.surface.get_width()) as f64, scale);
+                    context.set_source_surface(&self.surface, 0.0, 0.0);
+                    context.paint();
+                }
+                (scaled, (0, height), width)
+            },
+        };
+
+        RenderedGlyph {
+            surface: surface,
+            codepoint: Some(self.codepoint),
+            face: 0,
+            glyph_index: 0,
+            image: true,
+            codepoints: Vec::new(),
+            advance: (advance, 0),
+            offset: offset,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Overlays and descriptions for inspecting single glyphs of an atlas, so wrong rects, offsets or
+//! padding can be spotted in the atlas view.
+
+use cairo::{Context};
+
+use glyphs::{Atlas, GlyphInfo};
+use render_settings::{RenderSettings};
+
+/// Outlines every glyph of the page in blue, its bitmap inside the padding in green and its
+/// baseline in red.
+pub fn draw_overlays(cr: &Context, atlas: &Atlas, page: u32, padding: u32) {
+    cr.set_line_width(1.0);
+
+    for glyph in atlas.glyphs.iter().filter(|g| g.page == page && g.width > 0) {
+        let (x, y, width, height) = glyph.rect();
+        outline(cr, (x, y, width, height), (0.3, 0.5, 1.0));
+        if padding > 0 && width > 2 * padding && height > 2 * padding {
+            outline(cr, (x + padding, y + padding, width - 2 * padding, height - 2 * padding), (0.2, 0.7, 0.3));
+        }
+
+        cr.set_source_rgb(1.0, 0.3, 0.3);
+        if glyph.rotated {
+            // The top of the bitmap ends up on the right
+            let bitmap_height = glyph.height as i32 - 2 * padding as i32;
+            let baseline = (x + padding) as i32 + bitmap_height - glyph.yoffset;
+            cr.move_to(baseline as f64 + 0.5, y as f64);
+            cr.line_to(baseline as f64 + 0.5, (y + height) as f64);
+        } else {
+            let baseline = (y + padding) as i32 + glyph.yoffset;
+            cr.move_to(x as f64, baseline as f64 + 0.5);
+            cr.line_to((x + width) as f64, baseline as f64 + 0.5);
+        }
+        cr.stroke();
+    }
+}
+
+/// Strokes a one pixel wide outline just inside the rect.
+fn outline(cr: &Context, rect: (u32, u32, u32, u32), color: (f64, f64, f64)) {
+    let (x, y, width, height) = rect;
+    cr.rectangle(x as f64 + 0.5, y as f64 + 0.5, width as f64 - 1.0, height as f64 - 1.0);
+    cr.set_source_rgb(color.0, color.1, color.2);
+    cr.stroke();
+}
+
+/// Highlights the rect of the glyph.
+pub fn draw_selection(cr: &Context, glyph: &GlyphInfo) {
+    let (x, y, width, height) = glyph.rect();
+    cr.rectangle(x as f64, y as f64, width as f64, height as f64);
+    cr.set_source_rgba(1.0, 0.6, 0.0, 0.3);
+    cr.fill_preserve();
+    cr.set_source_rgb(1.0, 0.6, 0.0);
+    cr.set_line_width(2.0);
+    cr.stroke();
+}
+
+/// Properties of a glyph as shown in the inspector, offsets and advance as exported to the font
+/// files, with the glyph adjustments of the render settings applied.
+pub struct Description {
+    pub glyph: String,
+    pub glyph_index: String,
+    pub rect: String,
+    pub offset: String,
+    pub advance: String,
+    pub bearing: String,
+}
+
+impl Description {
+    pub fn new(glyph: &GlyphInfo, atlas: &Atlas, render_settings: &RenderSettings) -> Description {
+        let font_size = render_settings.variants()[glyph.variant].font_size;
+        // Only chars get adjusted, glyphs by index keep their metrics
+        let (adjust_x, adjust_y, adjust_advance) = match glyph.codepoint {
+            Some(codepoint) => {
+                let adjustment = render_settings.glyph_adjustment(codepoint);
+                (adjustment.xoffset, adjustment.yoffset, adjustment.xadvance)
+            },
+            None => (0, 0, 0),
+        };
+
+        let name = match glyph.codepoint {
+            Some(codepoint) => {
+                let character = char::from_u32(codepoint as u32).map(|c| c.to_string()).unwrap_or_default();
+                format!("U+{:04X} {}", codepoint, character)
+            },
+            None => format!("Glyph {}", glyph.glyph_index),
+        };
+
+        let glyph_index = match (glyph.image, glyph.face) {
+            (true, _) => "Image".to_string(),
+            (false, 0) => glyph.glyph_index.to_string(),
+            (false, face) => format!("{} in fallback font {}", glyph.glyph_index, face),
+        };
+
+        let rect = if glyph.width == 0 {
+            "Empty".to_string()
+        } else {
+            let (x, y, width, height) = glyph.rect();
+            format!("{}×{} at {}, {} on page {}{}", width, height, x, y, glyph.page, if glyph.rotated { ", rotated" } else { "" })
+        };
+
+        let padding = render_settings.letter_padding as i32;
+        let base = atlas.metrics.get(glyph.variant).map(|m| m.ascender).unwrap_or(0);
+        let xoffset = glyph.xoffset + adjust_x;
+        let yoffset = glyph.yoffset - adjust_y;
+        let xadvance = glyph.xadvance + adjust_advance;
+        let (offset, bearing) = if glyph.width == 0 {
+            (format!("{}, {}", adjust_x, adjust_y), "None".to_string())
+        } else {
+            let bitmap_width = glyph.width as i32 - 2 * padding;
+            let right = xadvance - xoffset - bitmap_width;
+            (format!("{}, {}", xoffset - padding, base - yoffset - padding),
+             format!("left {}, right {}, top {}", xoffset, right, yoffset))
+        };
+
+        Description {
+            glyph: format!("{} at {} px", name, font_size),
+            glyph_index: glyph_index,
+            rect: rect,
+            offset: offset,
+            advance: xadvance.to_string(),
+            bearing: bearing,
+        }
+    }
+}
@@ -0,0 +1,461 @@
+//! Module for writing the font descriptor as JSON.
+//!
+//! The file contains a single object with the same data as the BMFont text format, with keys
+//! named like the BMFont attributes:
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "info": { "face": string, "size": int, "bold": bool, "italic": bool, "charset": string,
+//!             "unicode": bool, "stretchH": int, "smooth": bool, "aa": bool,
+//!             "padding": [up, right, down, left], "spacing": [horizontal, vertical], "outline": int },
+//!   "common": { "lineHeight": int, "base": int, "scaleW": int, "scaleH": int, "pages": int,
+//!               "packed": bool, "alphaChnl": int, "redChnl": int, "greenChnl": int, "blueChnl": int },
+//!   "pages": [ { "id": int, "file": string } ],
+//!   "chars": [ { "id": int, "x": int, "y": int, "width": int, "height": int, "xoffset": int,
+//!                "yoffset": int, "xadvance": int, "page": int, "chnl": int, "rotated": bool } ],
+//!   "kernings": [ { "first": int, "second": int, "amount": int } ],
+//!   "glyphs": [ { "id": int, ..., "codepoints": [int] } ],
+//!   "substitutions": [ { "codepoints": [int], "before": int, "after": int, "glyphs": [int],
+//!                        "positions": [ { "xoffset": int, "yoffset": int,
+//!                                         "xadvance": int, "yadvance": int } ] } ]
+//! }
+//! ```
+//!
+//! A rotated char is stored turned 90 degrees clockwise and covers `height` pixels to the right
+//! and `width` pixels down from `x` and `y`.
+//!
+//! `glyphs` is only present for shaped text or glyphs requested by index and `substitutions` only
+//! for shaped text. `glyphs` has the same keys as `chars`, but the id is the index of the glyph
+//! in the font instead of a codepoint, and `codepoints` lists the codepoints the font maps to
+//! the glyph, none for unencoded glyphs like alternates or small caps. Each substitution is a
+//! run of codepoints that shaping maps to the listed glyph ids instead of the chars of the single
+//! codepoints, like a ligature or the contextual form of a letter. A contextual form depends on
+//! the neighbors of the run, so the same run can be listed several times with the codepoints
+//! right `before` and `after` it in the shaped text, which are left out at the start or end of a
+//! line. `positions` has an entry for each glyph, the offset from the text cursor to add to the
+//! offsets of the glyph and the distance to move the cursor after it instead of its `xadvance`,
+//! with y pointing down like `yoffset`.
+//!
+//! `version` is increased whenever existing keys change their meaning or get removed. New keys
+//! can be added without increasing it, so readers should ignore keys they don't know.
+//!
+
+use std::io;
+
+use font::{CharTag, FontFile};
+
+/// Version of the schema described in the module documentation
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub fn write(font_file: &FontFile, write: &mut io::Write) -> io::Result<()> {
+    let mut out = JsonWriter::new(write);
+    let info = &font_file.info;
+    let common = &font_file.common;
+
+    out.begin_object()?;
+    out.key("version")?;
+    out.number(SCHEMA_VERSION as i64)?;
+
+    out.key("info")?;
+    out.begin_object()?;
+    out.key("face")?;
+    out.string(&info.face)?;
+    out.key("size")?;
+    out.number(info.size as i64)?;
+    out.key("bold")?;
+    out.bool(info.bold)?;
+    out.key("italic")?;
+    out.bool(info.italic)?;
+    out.key("charset")?;
+    out.string(&info.charset)?;
+    out.key("unicode")?;
+    out.bool(info.unicode)?;
+    out.key("stretchH")?;
+    out.number(info.stretch_h as i64)?;
+    out.key("smooth")?;
+    out.bool(info.smooth)?;
+    out.key("aa")?;
+    out.bool(info.aa)?;
+    out.key("padding")?;
+    out.numbers(&info.padding.iter().map(|&p| p as i64).collect::<Vec<i64>>())?;
+    out.key("spacing")?;
+    out.numbers(&info.spacing.iter().map(|&s| s as i64).collect::<Vec<i64>>())?;
+    out.key("outline")?;
+    out.number(info.outline as i64)?;
+    out.end_object()?;
+
+    out.key("common")?;
+    out.begin_object()?;
+    out.key("lineHeight")?;
+    out.number(common.line_height as i64)?;
+    out.key("base")?;
+    out.number(common.base as i64)?;
+    out.key("scaleW")?;
+    out.number(common.scale_w as i64)?;
+    out.key("scaleH")?;
+    out.number(common.scale_h as i64)?;
+    out.key("pages")?;
+    out.number(common.pages as i64)?;
+    out.key("packed")?;
+    out.bool(common.packed)?;
+    out.key("alphaChnl")?;
+    out.number(common.alpha_channel as i64)?;
+    out.key("redChnl")?;
+    out.number(common.red_channel as i64)?;
+    out.key("greenChnl")?;
+    out.number(common.green_channel as i64)?;
+    out.key("blueChnl")?;
+    out.number(common.blue_channel as i64)?;
+    out.end_object()?;
+
+    out.key("pages")?;
+    out.begin_array()?;
+    for page in &font_file.pages {
+        out.begin_object()?;
+        out.key("id")?;
+        out.number(page.id as i64)?;
+        out.key("file")?;
+        out.string(&page.file)?;
+        out.end_object()?;
+    }
+    out.end_array()?;
+
+    out.key("chars")?;
+    out.begin_array()?;
+    for char in &font_file.chars {
+        write_char(char, None, &mut out)?;
+    }
+    out.end_array()?;
+
+    out.key("kernings")?;
+    out.begin_array()?;
+    for kerning in &font_file.kernings {
+        out.begin_object()?;
+        out.key("first")?;
+        out.number(kerning.first as i64)?;
+        out.key("second")?;
+        out.number(kerning.second as i64)?;
+        out.key("amount")?;
+        out.number(kerning.amount as i64)?;
+        out.end_object()?;
+    }
+    out.end_array()?;
+
+    if !font_file.glyphs.is_empty() {
+        out.key("glyphs")?;
+        out.begin_array()?;
+        for glyph in &font_file.glyphs {
+            write_char(&glyph.char, Some(&glyph.codepoints), &mut out)?;
+        }
+        out.end_array()?;
+    }
+
+    if !font_file.substitutions.is_empty() {
+        out.key("substitutions")?;
+        out.begin_array()?;
+        for substitution in &font_file.substitutions {
+            out.begin_object()?;
+            out.key("codepoints")?;
+            out.numbers(&substitution.codepoints.iter().map(|&c| c as i64).collect::<Vec<i64>>())?;
+            if let Some(before) = substitution.before {
+                out.key("before")?;
+                out.number(before as i64)?;
+            }
+            if let Some(after) = substitution.after {
+                out.key("after")?;
+                out.number(after as i64)?;
+            }
+            out.key("glyphs")?;
+            out.numbers(&substitution.glyphs.iter().map(|&g| g as i64).collect::<Vec<i64>>())?;
+            out.key("positions")?;
+            out.begin_array()?;
+            for (offset, advance) in substitution.offsets.iter().zip(&substitution.advances) {
+                out.begin_object()?;
+                out.key("xoffset")?;
+                out.number(offset.0 as i64)?;
+                out.key("yoffset")?;
+                out.number(offset.1 as i64)?;
+                out.key("xadvance")?;
+                out.number(advance.0 as i64)?;
+                out.key("yadvance")?;
+                out.number(advance.1 as i64)?;
+                out.end_object()?;
+            }
+            out.end_array()?;
+            out.end_object()?;
+        }
+        out.end_array()?;
+    }
+
+    out.end_object()?;
+    out.newline()
+}
+
+fn write_char(char: &CharTag, codepoints: Option<&[usize]>, out: &mut JsonWriter) -> io::Result<()> {
+    out.begin_object()?;
+    out.key("id")?;
+    out.number(char.id as i64)?;
+    out.key("x")?;
+    out.number(char.x as i64)?;
+    out.key("y")?;
+    out.number(char.y as i64)?;
+    out.key("width")?;
+    out.number(char.width as i64)?;
+    out.key("height")?;
+    out.number(char.height as i64)?;
+    out.key("xoffset")?;
+    out.number(char.xoffset as i64)?;
+    out.key("yoffset")?;
+    out.number(char.yoffset as i64)?;
+    out.key("xadvance")?;
+    out.number(char.xadvance as i64)?;
+    out.key("page")?;
+    out.number(char.page as i64)?;
+    out.key("chnl")?;
+    out.number(char.chnl as i64)?;
+    out.key("rotated")?;
+    out.bool(char.rotated)?;
+    if let Some(codepoints) = codepoints {
+        out.key("codepoints")?;
+        out.numbers(&codepoints.iter().map(|&c| c as i64).collect::<Vec<i64>>())?;
+    }
+    out.end_object()
+}
+
+/// Writes JSON values one token at a time, taking care of separators and indentation.
+pub struct JsonWriter<'a> {
+    writer: &'a mut io::Write,
+    /// For each open object or array whether it already contains a value
+    scopes: Vec<bool>,
+    after_key: bool,
+}
+
+impl<'a> JsonWriter<'a> {
+    pub fn new(writer: &'a mut io::Write) -> JsonWriter<'a> {
+        JsonWriter {
+            writer: writer,
+            scopes: Vec::new(),
+            after_key: false,
+        }
+    }
+
+    pub fn begin_object(&mut self) -> io::Result<()> {
+        self.value_prefix()?;
+        self.scopes.push(false);
+        self.writer.write_all(b"{")
+    }
+
+    pub fn end_object(&mut self) -> io::Result<()> {
+        self.end_scope(b"}")
+    }
+
+    pub fn begin_array(&mut self) -> io::Result<()> {
+        self.value_prefix()?;
+        self.scopes.push(false);
+        self.writer.write_all(b"[")
+    }
+
+    pub fn end_array(&mut self) -> io::Result<()> {
+        self.end_scope(b"]")
+    }
+
+    pub fn key(&mut self, key: &str) -> io::Result<()> {
+        self.value_prefix()?;
+        self.write_escaped(key)?;
+        self.writer.write_all(b": ")?;
+        self.after_key = true;
+        Ok(())
+    }
+
+    pub fn string(&mut self, value: &str) -> io::Result<()> {
+        self.value_prefix()?;
+        self.write_escaped(value)
+    }
+
+    pub fn number(&mut self, value: i64) -> io::Result<()> {
+        self.value_prefix()?;
+        self.writer.write_all(value.to_string().as_bytes())
+    }
+
+    pub fn float(&mut self, value: f64) -> io::Result<()> {
+        self.value_prefix()?;
+        self.writer.write_all(value.to_string().as_bytes())
+    }
+
+    pub fn bool(&mut self, value: bool) -> io::Result<()> {
+        self.value_prefix()?;
+        self.writer.write_all(if value { b"true" } else { b"false" })
+    }
+
+    /// Writes an array of numbers on a single line.
+    pub fn numbers(&mut self, values: &[i64]) -> io::Result<()> {
+        self.value_prefix()?;
+        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        self.writer.write_all(b"[")?;
+        self.writer.write_all(values.join(", ").as_bytes())?;
+        self.writer.write_all(b"]")
+    }
+
+    pub fn newline(&mut self) -> io::Result<()> {
+        self.writer.write_all(b"\n")
+    }
+
+    fn value_prefix(&mut self) -> io::Result<()> {
+        if self.after_key {
+            self.after_key = false;
+            return Ok(());
+        }
+
+        if let Some(has_values) = self.scopes.last_mut() {
+            if *has_values {
+                self.writer.write_all(b",")?;
+            }
+            *has_values = true;
+        }
+        if !self.scopes.is_empty() {
+            self.indent(self.scopes.len())?;
+        }
+        Ok(())
+    }
+
+    fn end_scope(&mut self, token: &[u8]) -> io::Result<()> {
+        let has_values = self.scopes.pop().unwrap_or(false);
+        if has_values {
+            self.indent(self.scopes.len())?;
+        }
+        self.writer.write_all(token)
+    }
+
+    fn indent(&mut self, depth: usize) -> io::Result<()> {
+        self.writer.write_all(b"\n")?;
+        for _ in 0..depth {
+            self.writer.write_all(b"  ")?;
+        }
+        Ok(())
+    }
+
+    fn write_escaped(&mut self, value: &str) -> io::Result<()> {
+        let mut escaped = String::with_capacity(value.len() + 2);
+        escaped.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\t' => escaped.push_str("\\t"),
+                c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                c => escaped.push(c),
+            }
+        }
+        escaped.push('"');
+        self.writer.write_all(escaped.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use font::{SubstitutionTag};
+
+    fn written<F: FnOnce(&mut JsonWriter) -> io::Result<()>>(f: F) -> String {
+        let mut buffer = Vec::new();
+        f(&mut JsonWriter::new(&mut buffer)).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let json = written(|out| out.string("a\"b\\c\nd\te\u{1}f\u{e9}"));
+        assert_eq!(json, "\"a\\\"b\\\\c\\nd\\te\\u0001f\u{e9}\"");
+    }
+
+    #[test]
+    fn separates_values_and_indents_scopes() {
+        let json = written(|out| {
+            out.begin_object()?;
+            out.key("a")?;
+            out.number(1)?;
+            out.key("b")?;
+            out.begin_array()?;
+            out.bool(true)?;
+            out.numbers(&[1, 2])?;
+            out.end_array()?;
+            out.end_object()
+        });
+        assert_eq!(json, "{\n  \"a\": 1,\n  \"b\": [\n    true,\n    [1, 2]\n  ]\n}");
+    }
+
+    #[test]
+    fn writes_empty_scopes_on_one_line() {
+        let json = written(|out| {
+            out.begin_object()?;
+            out.key("chars")?;
+            out.begin_array()?;
+            out.end_array()?;
+            out.key("info")?;
+            out.begin_object()?;
+            out.end_object()?;
+            out.end_object()
+        });
+        assert_eq!(json, "{\n  \"chars\": [],\n  \"info\": {}\n}");
+    }
+
+    #[test]
+    fn writes_context_and_positions_of_substitutions() {
+        let mut font_file = FontFile::parse(&mut "info face=A\ncommon\n".as_bytes()).unwrap();
+        // The initial and the final form of U+0628 in U+0628 U+0628
+        font_file.substitutions.push(SubstitutionTag {
+            codepoints: vec![0x628],
+            before: None,
+            after: Some(0x628),
+            glyphs: vec![12],
+            offsets: vec![(0, 0)],
+            advances: vec![(5, 0)],
+        });
+        font_file.substitutions.push(SubstitutionTag {
+            codepoints: vec![0x628],
+            before: Some(0x628),
+            after: None,
+            glyphs: vec![13],
+            offsets: vec![(1, -2)],
+            advances: vec![(7, 0)],
+        });
+
+        let mut buffer = Vec::new();
+        write(&font_file, &mut buffer).unwrap();
+        let json = String::from_utf8(buffer).unwrap();
+        let substitutions = &json[json.find("\"substitutions\"").unwrap()..];
+        assert_eq!(substitutions, "\"substitutions\": [
+    {
+      \"codepoints\": [1576],
+      \"after\": 1576,
+      \"glyphs\": [12],
+      \"positions\": [
+        {
+          \"xoffset\": 0,
+          \"yoffset\": 0,
+          \"xadvance\": 5,
+          \"yadvance\": 0
+        }
+      ]
+    },
+    {
+      \"codepoints\": [1576],
+      \"before\": 1576,
+      \"glyphs\": [13],
+      \"positions\": [
+        {
+          \"xoffset\": 1,
+          \"yoffset\": -2,
+          \"xadvance\": 7,
+          \"yadvance\": 0
+        }
+      ]
+    }
+  ]
+}
+");
+    }
+}
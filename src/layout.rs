@@ -0,0 +1,331 @@
+//! Lays out text with a bitmap font as quads copying glyph rects from the pages to their place
+//! in the text, following AngelCode's [text rendering page][1], for renderers that draw text
+//! from a `FontFile`.
+//!
+//! [1]: http://www.angelcode.com/products/bmfont/doc/render_text.html
+//!
+
+use std::cmp;
+
+use font::{CharTag, FontFile};
+
+/// Horizontal alignment of the lines of a text.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+    /// Widens the spaces so lines reach the maximum width, except the last line of a paragraph
+    Justify,
+}
+
+impl Align {
+    pub fn all() -> &'static [Align] {
+        static ALL: [Align; 4] = [Align::Left, Align::Center, Align::Right, Align::Justify];
+        &ALL
+    }
+
+    /// Human readable name, used in the preview pane
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Align::Left => "Left",
+            Align::Center => "Center",
+            Align::Right => "Right",
+            Align::Justify => "Justify",
+        }
+    }
+
+    /// Identifier used on the command line
+    pub fn id(&self) -> &'static str {
+        match *self {
+            Align::Left => "left",
+            Align::Center => "center",
+            Align::Right => "right",
+            Align::Justify => "justify",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Align> {
+        Align::all().iter().cloned().find(|a| a.id() == id)
+    }
+}
+
+/// A glyph rect copied from a page to its place in the text.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Quad {
+    pub codepoint: usize,
+    pub page: u32,
+    /// Rect in the page, x, y, width and height
+    pub source: (u32, u32, u32, u32),
+    /// The source rect holds the glyph turned 90 degrees clockwise
+    pub rotated: bool,
+    /// Rect relative to the top-left corner of the text, x, y, width and height
+    pub destination: (i32, i32, u32, u32),
+}
+
+pub struct Layout {
+    /// Width lines get broken at, between words where possible. `None` only breaks at newlines.
+    pub max_width: Option<i32>,
+    pub align: Align,
+    /// Distance between tab stops, 0 uses four times the advance of space
+    pub tab_width: i32,
+}
+
+impl Layout {
+    pub fn new() -> Layout {
+        Layout {
+            max_width: None,
+            align: Align::Left,
+            tab_width: 0,
+        }
+    }
+
+    /// Lays out the text in lines `lineHeight` apart. Characters the font doesn't contain are
+    /// skipped and characters without pixels, like space, only advance the text cursor.
+    pub fn quads(&self, font_file: &FontFile, text: &str) -> Vec<Quad> {
+        let lines: Vec<(Vec<char>, bool)> = text.split('\n').flat_map(|paragraph| self.break_lines(font_file, paragraph)).collect();
+        let widths: Vec<i32> = lines.iter().map(|&(ref line, _)| self.width(font_file, line)).collect();
+        let block_width = self.max_width.unwrap_or_else(|| widths.iter().cloned().max().unwrap_or(0));
+        let mut quads = Vec::new();
+
+        for (index, (&(ref line, last), width)) in lines.iter().zip(widths).enumerate() {
+            let free = block_width - width;
+            let (left, extra) = match self.align {
+                Align::Left => (0, 0),
+                Align::Center => (free / 2, 0),
+                Align::Right => (free, 0),
+                Align::Justify if !last => (0, cmp::max(0, free)),
+                Align::Justify => (0, 0),
+            };
+            let top = index as i32 * font_file.common.line_height as i32;
+
+            for (pen, char) in self.positions(font_file, line, extra).0 {
+                if char.width == 0 || char.height == 0 {
+                    continue
+                }
+                quads.push(Quad {
+                    codepoint: char.id,
+                    page: char.page,
+                    source: if char.rotated {
+                        (char.x, char.y, char.height, char.width)
+                    } else {
+                        (char.x, char.y, char.width, char.height)
+                    },
+                    rotated: char.rotated,
+                    destination: (left + pen + char.xoffset, top + char.yoffset, char.width, char.height),
+                });
+            }
+        }
+
+        quads
+    }
+
+    /// Breaks a paragraph into lines no wider than `max_width`, between words or inside words
+    /// that don't fit a line on their own. Whitespace at the end of lines and at the start of
+    /// wrapped lines is dropped. The flag marks the last line of the paragraph.
+    fn break_lines(&self, font_file: &FontFile, paragraph: &str) -> Vec<(Vec<char>, bool)> {
+        let max_width = match self.max_width {
+            Some(max_width) => max_width,
+            None => return vec![(paragraph.chars().collect(), true)],
+        };
+        let mut lines = Vec::new();
+        let mut line: Vec<char> = Vec::new();
+
+        for word in Layout::words(paragraph) {
+            let space = word[0].is_whitespace();
+            if space && line.is_empty() && !lines.is_empty() {
+                continue
+            }
+
+            let mut candidate = line.clone();
+            candidate.extend_from_slice(&word);
+            if space || self.width(font_file, &candidate) <= max_width {
+                line = candidate;
+                continue
+            }
+
+            if line.iter().any(|c| !c.is_whitespace()) {
+                lines.push((Layout::trim_end(line), false));
+                line = Vec::new();
+            }
+            for c in word {
+                let mut candidate = line.clone();
+                candidate.push(c);
+                if !line.is_empty() && self.width(font_file, &candidate) > max_width {
+                    lines.push((line, false));
+                    line = vec![c];
+                } else {
+                    line = candidate;
+                }
+            }
+        }
+
+        lines.push((Layout::trim_end(line), true));
+        lines
+    }
+
+    /// Text cursor position of each character of the line the font contains and the position
+    /// after the last one. `extra` pixels are spread over the spaces.
+    fn positions<'a>(&self, font_file: &'a FontFile, line: &[char], extra: i32) -> (Vec<(i32, &'a CharTag)>, i32) {
+        let spaces = line.iter().filter(|&&c| c == ' ').count() as i32;
+        let tab_width = self.tab_width(font_file);
+        let mut positions = Vec::new();
+        let mut pen = 0;
+        let mut space_index = 0;
+        let mut previous = None;
+
+        for &c in line {
+            if c == '\t' {
+                pen = (pen / tab_width + 1) * tab_width;
+                previous = None;
+                continue
+            }
+            let char = match font_file.chars.iter().find(|char| char.id == c as usize) {
+                Some(char) => char,
+                None => continue,
+            };
+            if let Some(first) = previous {
+                pen += font_file.kernings.iter()
+                    .find(|k| k.first == first && k.second == char.id)
+                    .map(|k| k.amount)
+                    .unwrap_or(0);
+            }
+
+            positions.push((pen, char));
+            pen += char.xadvance;
+            if c == ' ' {
+                pen += extra * (space_index + 1) / spaces - extra * space_index / spaces;
+                space_index += 1;
+            }
+            previous = Some(char.id);
+        }
+
+        (positions, pen)
+    }
+
+    fn width(&self, font_file: &FontFile, line: &[char]) -> i32 {
+        self.positions(font_file, line, 0).1
+    }
+
+    fn tab_width(&self, font_file: &FontFile) -> i32 {
+        if self.tab_width > 0 {
+            return self.tab_width;
+        }
+        let space_advance = font_file.chars.iter().find(|c| c.id == ' ' as usize).map(|c| c.xadvance);
+        cmp::max(1, 4 * space_advance.unwrap_or(font_file.common.line_height as i32 / 4))
+    }
+
+    /// Splits the text into runs of whitespace and runs of other characters.
+    fn words(text: &str) -> Vec<Vec<char>> {
+        let mut words: Vec<Vec<char>> = Vec::new();
+        for c in text.chars() {
+            let same_kind = words.last().map(|w| w[0].is_whitespace() == c.is_whitespace()).unwrap_or(false);
+            if same_kind {
+                words.last_mut().expect("Word").push(c);
+            } else {
+                words.push(vec![c]);
+            }
+        }
+        words
+    }
+
+    fn trim_end(mut line: Vec<char>) -> Vec<char> {
+        while line.last().map(|c| c.is_whitespace()).unwrap_or(false) {
+            line.pop();
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 'a' and 'b' are 4 pixels wide and advance 5, space advances 3 and "ab" kerns by -1
+    fn font_file() -> FontFile {
+        let text = "info face=Test size=8
+common lineHeight=10 base=8 scaleW=64 scaleH=64 pages=1
+char id=32 x=0 y=0 width=0 height=0 xoffset=0 yoffset=0 xadvance=3
+char id=97 x=0 y=0 width=4 height=6 xoffset=0 yoffset=2 xadvance=5
+char id=98 x=5 y=0 width=4 height=7 xoffset=1 yoffset=1 xadvance=5
+kerning first=97 second=98 amount=-1
+";
+        FontFile::parse(&mut text.as_bytes()).unwrap()
+    }
+
+    fn layout(max_width: Option<i32>, align: Align) -> Layout {
+        Layout {
+            max_width: max_width,
+            align: align,
+            tab_width: 0,
+        }
+    }
+
+    fn positions(quads: &[Quad]) -> Vec<(i32, i32)> {
+        quads.iter().map(|q| (q.destination.0, q.destination.1)).collect()
+    }
+
+    #[test]
+    fn places_quads_with_kerning() {
+        let quads = layout(None, Align::Left).quads(&font_file(), "ab a");
+        assert_eq!(positions(&quads), vec![(0, 2), (5, 1), (12, 2)]);
+        assert_eq!(quads[1].source, (5, 0, 4, 7));
+        assert_eq!(quads[1].codepoint, 'b' as usize);
+    }
+
+    #[test]
+    fn aligns_lines() {
+        let font_file = font_file();
+        assert_eq!(positions(&layout(Some(20), Align::Left).quads(&font_file, "ab")), vec![(0, 2), (5, 1)]);
+        assert_eq!(positions(&layout(Some(20), Align::Center).quads(&font_file, "ab")), vec![(5, 2), (10, 1)]);
+        assert_eq!(positions(&layout(Some(20), Align::Right).quads(&font_file, "ab")), vec![(11, 2), (16, 1)]);
+        // Without a maximum width the widest line is the block
+        assert_eq!(positions(&layout(None, Align::Right).quads(&font_file, "aa\nb")), vec![(0, 2), (5, 2), (6, 11)]);
+    }
+
+    #[test]
+    fn justifies_all_but_the_last_line() {
+        let quads = layout(Some(14), Align::Justify).quads(&font_file(), "a a a a");
+        assert_eq!(positions(&quads), vec![(0, 2), (9, 2), (0, 12), (8, 12)]);
+    }
+
+    #[test]
+    fn spreads_justification_over_spaces() {
+        let quads = layout(Some(24), Align::Justify).quads(&font_file(), "a a a a a");
+        assert_eq!(positions(&quads), vec![(0, 2), (9, 2), (19, 2), (0, 12), (8, 12)]);
+    }
+
+    #[test]
+    fn breaks_between_words() {
+        let quads = layout(Some(12), Align::Left).quads(&font_file(), "ab ab");
+        assert_eq!(positions(&quads), vec![(0, 2), (5, 1), (0, 12), (5, 11)]);
+    }
+
+    #[test]
+    fn breaks_words_wider_than_a_line() {
+        let quads = layout(Some(12), Align::Left).quads(&font_file(), "aaaaa");
+        assert_eq!(positions(&quads), vec![(0, 2), (5, 2), (0, 12), (5, 12), (0, 22)]);
+    }
+
+    #[test]
+    fn trims_whitespace_at_line_ends() {
+        let quads = layout(Some(20), Align::Right).quads(&font_file(), "ab   ");
+        assert_eq!(positions(&quads), vec![(11, 2), (16, 1)]);
+    }
+
+    #[test]
+    fn advances_to_tab_stops() {
+        let font_file = font_file();
+        // Four spaces by default, and kerning doesn't apply across the tab
+        assert_eq!(positions(&layout(None, Align::Left).quads(&font_file, "a\tb")), vec![(0, 2), (13, 1)]);
+        let mut layout = layout(None, Align::Left);
+        layout.tab_width = 8;
+        assert_eq!(positions(&layout.quads(&font_file, "aa\tb\ta")), vec![(0, 2), (5, 2), (17, 1), (24, 2)]);
+    }
+
+    #[test]
+    fn skips_missing_characters() {
+        let quads = layout(None, Align::Left).quads(&font_file(), "axa");
+        assert_eq!(positions(&quads), vec![(0, 2), (5, 2)]);
+    }
+}
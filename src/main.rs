@@ -1,18 +1,40 @@
 extern crate cairo;
 extern crate freetype as ft;
 extern crate gdk;
+extern crate gdk_pixbuf;
 extern crate glib;
 extern crate gtk;
 
+mod cli;
+mod color;
+mod export;
 mod font;
 mod glyph;
 mod glyphs;
+mod godot;
+mod grid;
+mod hiero;
+mod image;
+mod image_glyph;
+mod inspector;
+mod json;
+mod layout;
 #[macro_use]
 mod macros;
 mod main_window;
+mod opentype;
+mod packer;
+mod preview;
 mod render_settings;
+mod sfnt;
+mod shaping;
+mod source;
+mod text_mesh_pro;
+mod variation;
 
 use std::cell::RefCell;
+use std::env;
+use std::process;
 use std::rc::Rc;
 use render_settings::{RenderSettings};
 use main_window::{MainWindow};
@@ -20,6 +42,15 @@ use main_window::{MainWindow};
 static APP_NAME: &'static str = "font-atlas";
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(message) = cli::Options::parse(args.into_iter()).and_then(|options| cli::run(&options)) {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+        return;
+    }
+
     glib::set_prgname(Some(APP_NAME));
     glib::set_application_name(APP_NAME);
 
@@ -1,12 +1,21 @@
 use cairo::{self, Pattern};
-use gtk::{self, AboutDialog, ApplicationWindow, Builder, ColorButton, DrawingArea, FileChooserAction, FileChooserDialog, FontButton, ImageMenuItem, ResponseType, SpinButton, TextView};
+use gtk::{self, AboutDialog, ApplicationWindow, Builder, Button, ButtonsType, CheckButton, ColorButton, ComboBoxText, DialogFlags, DrawingArea, Entry, Expander, FileChooserAction, FileChooserButton, FileChooserDialog, FileFilter, FontButton, ImageMenuItem, Label, MessageDialog, MessageType, Orientation, ResponseType, SpinButton, TextView};
 use gtk::prelude::*;
-use std::cell::RefCell;
+use std::cell::{RefCell};
 use std::path::{Path};
 use std::rc::Rc;
 
-use glyphs::{Glyphs};
+use export::{ExportFormat};
+use font::{FontFile};
+use glyphs::{Atlas, GlyphInfo, Glyphs};
+use image::{Extrude, ImageFormat, PixelFormat};
+use image_glyph::{ImageGlyph};
+use inspector;
+use layout::{Align, Layout};
+use preview;
 use render_settings::{RenderSettings};
+use shaping::{Feature};
+use variation::{self, Variations};
 
 pub struct MainWindow {
     window: ApplicationWindow,
@@ -37,13 +46,41 @@ impl MainWindow {
                 ("Save", ResponseType::Ok.into()),
                 ("Cancel", ResponseType::Cancel.into())
             ]);
+            for format in ExportFormat::all() {
+                let filter = FileFilter::new();
+                FileFilterExt::set_name(&filter, format.name());
+                filter.add_pattern(&format!("*.{}", format.extension()));
+                file_chooser.add_filter(&filter);
+            }
             if file_chooser.run() == ResponseType::Ok.into() {
                 let filename = file_chooser.get_filename().expect("Couldn't get filename");
+                // Formats can share an extension, so the selected filter wins when it matches
+                let selected = file_chooser.get_filter().and_then(|f| FileFilterExt::get_name(&f)).and_then(|n| ExportFormat::from_name(&n));
+                let format = selected.filter(|f| f.strip_extension(&filename) != filename)
+                    .or_else(|| ExportFormat::from_path(&filename))
+                    .or(selected)
+                    .unwrap_or(ExportFormat::BMFont);
                 let render_settings = render_settings.borrow();
                 let width = drawing_area.get_allocated_width();
                 let height = drawing_area.get_allocated_height();
                 let glyphs = Glyphs::new();
-                glyphs.write_to_file(&filename, &render_settings, width, height).expect("Succeeds");
+                let missing = match glyphs.write_to_file(&filename, &render_settings, width, height, format) {
+                    Ok(missing) => missing,
+                    Err(err) => {
+                        let dialog = MessageDialog::new(Some(&window), DialogFlags::MODAL, MessageType::Error, ButtonsType::Ok,
+                            &format!("Couldn't export {}:\n{}", filename.display(), err));
+                        dialog.run();
+                        dialog.destroy();
+                        Vec::new()
+                    },
+                };
+                if !missing.is_empty() {
+                    let codepoints: Vec<String> = missing.iter().map(|c| format!("U+{:04X}", c)).collect();
+                    let dialog = MessageDialog::new(Some(&window), DialogFlags::MODAL, MessageType::Warning, ButtonsType::Ok,
+                        &format!("No font contains these characters or they are outside the grid, they were not exported:\n{}", codepoints.join(" ")));
+                    dialog.run();
+                    dialog.destroy();
+                }
             }
             file_chooser.destroy();
         }));
@@ -98,6 +135,218 @@ impl MainWindow {
             drawing_area.queue_draw();
         }));
 
+        let font_extra_sizes_entry: Entry = builder.get_object("font_extra_sizes").expect("Couldn't get font extra sizes entry");
+        font_extra_sizes_entry.connect_changed(clone!(drawing_area, render_settings => move |entry| {
+            let text = entry.get_text().unwrap_or_default();
+            let sizes = text.split(|c: char| c == ',' || c.is_whitespace())
+                .filter_map(|s| s.parse::<isize>().ok())
+                .filter(|&size| size >= 4)
+                .collect();
+            (*render_settings.borrow_mut()).extra_sizes = sizes;
+            drawing_area.queue_draw();
+        }));
+
+        let font_extra_face_button: FileChooserButton = builder.get_object("font_extra_face").expect("Couldn't get font extra face button");
+        font_extra_face_button.connect_file_set(clone!(window, drawing_area, render_settings => move |btn| {
+            if let Some(filename) = btn.get_filename() {
+                let face = (*render_settings.borrow()).library.new_face(&filename, 0);
+                match face {
+                    Ok(face) => (*render_settings.borrow_mut()).extra_faces.push(Rc::new(face)),
+                    Err(err) => {
+                        let dialog = MessageDialog::new(Some(&window), DialogFlags::MODAL, MessageType::Error, ButtonsType::Ok,
+                            &format!("Couldn't load {}:\n{}", filename.display(), err));
+                        dialog.run();
+                        dialog.destroy();
+                    },
+                }
+                let names: Vec<String> = render_settings.borrow().extra_faces.iter()
+                    .map(|face| face.family_name().unwrap_or_default())
+                    .collect();
+                btn.set_tooltip_text(Some(names.join(", ").as_str()));
+                btn.unselect_all();
+                drawing_area.queue_draw();
+            }
+        }));
+
+        let font_extra_clear_button: Button = builder.get_object("font_extra_clear").expect("Couldn't get font extra clear button");
+        font_extra_clear_button.connect_clicked(clone!(drawing_area, render_settings, font_extra_face_button => move |_| {
+            (*render_settings.borrow_mut()).extra_faces.clear();
+            font_extra_face_button.set_tooltip_text(None);
+            drawing_area.queue_draw();
+        }));
+
+        let font_fallback_face_button: FileChooserButton = builder.get_object("font_fallback_face").expect("Couldn't get font fallback face button");
+        font_fallback_face_button.connect_file_set(clone!(window, drawing_area, render_settings => move |btn| {
+            if let Some(filename) = btn.get_filename() {
+                let face = (*render_settings.borrow()).library.new_face(&filename, 0);
+                match face {
+                    Ok(face) => (*render_settings.borrow_mut()).fallback_faces.push(Rc::new(face)),
+                    Err(err) => {
+                        let dialog = MessageDialog::new(Some(&window), DialogFlags::MODAL, MessageType::Error, ButtonsType::Ok,
+                            &format!("Couldn't load {}:\n{}", filename.display(), err));
+                        dialog.run();
+                        dialog.destroy();
+                    },
+                }
+                let names: Vec<String> = render_settings.borrow().fallback_faces.iter()
+                    .map(|face| face.family_name().unwrap_or_default())
+                    .collect();
+                btn.set_tooltip_text(Some(names.join(", ").as_str()));
+                btn.unselect_all();
+                drawing_area.queue_draw();
+            }
+        }));
+
+        let font_fallback_clear_button: Button = builder.get_object("font_fallback_clear").expect("Couldn't get font fallback clear button");
+        font_fallback_clear_button.connect_clicked(clone!(drawing_area, render_settings, font_fallback_face_button => move |_| {
+            (*render_settings.borrow_mut()).fallback_faces.clear();
+            font_fallback_face_button.set_tooltip_text(None);
+            drawing_area.queue_draw();
+        }));
+
+        let font_image_glyph_codepoint_spin_button: SpinButton = builder.get_object("font_image_glyph_codepoint").expect("Couldn't get image glyph codepoint spin button");
+        let font_image_glyph_button: FileChooserButton = builder.get_object("font_image_glyph").expect("Couldn't get image glyph button");
+        font_image_glyph_button.connect_file_set(clone!(window, drawing_area, render_settings, font_image_glyph_codepoint_spin_button => move |btn| {
+            if let Some(filename) = btn.get_filename() {
+                let codepoint = font_image_glyph_codepoint_spin_button.get_value() as usize;
+                match ImageGlyph::load(codepoint, &filename, None) {
+                    Ok(image_glyph) => {
+                        (*render_settings.borrow_mut()).image_glyphs.retain(|g| g.codepoint != codepoint);
+                        (*render_settings.borrow_mut()).image_glyphs.push(image_glyph);
+                        font_image_glyph_codepoint_spin_button.set_value((codepoint + 1) as f64);
+                    },
+                    Err(err) => {
+                        let dialog = MessageDialog::new(Some(&window), DialogFlags::MODAL, MessageType::Error, ButtonsType::Ok,
+                            &format!("Couldn't load {}:\n{}", filename.display(), err));
+                        dialog.run();
+                        dialog.destroy();
+                    },
+                }
+                let codepoints: Vec<String> = render_settings.borrow().image_glyphs.iter()
+                    .map(|g| format!("U+{:04X}", g.codepoint))
+                    .collect();
+                btn.set_tooltip_text(Some(codepoints.join(", ").as_str()));
+                btn.unselect_all();
+                drawing_area.queue_draw();
+            }
+        }));
+
+        let font_image_glyph_clear_button: Button = builder.get_object("font_image_glyph_clear").expect("Couldn't get image glyph clear button");
+        font_image_glyph_clear_button.connect_clicked(clone!(drawing_area, render_settings, font_image_glyph_button => move |_| {
+            (*render_settings.borrow_mut()).image_glyphs.clear();
+            font_image_glyph_button.set_tooltip_text(None);
+            drawing_area.queue_draw();
+        }));
+
+        let font_features_entry: Entry = builder.get_object("font_features").expect("Couldn't get font features entry");
+        let font_features_help = font_features_entry.get_tooltip_text().unwrap_or_default();
+        font_features_entry.connect_changed(clone!(drawing_area, render_settings => move |entry| {
+            let text = entry.get_text().unwrap_or_default();
+            let features: Vec<Feature> = text.split(|c: char| c == ',' || c.is_whitespace())
+                .filter_map(|tag| Feature::from_id(tag))
+                .collect();
+            // Shows the features that were recognized, or what can be entered
+            let names: Vec<String> = features.iter().map(|f| f.name()).collect();
+            if names.is_empty() {
+                entry.set_tooltip_text(Some(font_features_help.as_str()));
+            } else {
+                entry.set_tooltip_text(Some(names.join(", ").as_str()));
+            }
+            (*render_settings.borrow_mut()).features = features;
+            drawing_area.queue_draw();
+        }));
+
+        let font_color_glyphs_button: CheckButton = builder.get_object("font_color_glyphs").expect("Couldn't get font color glyphs button");
+        font_color_glyphs_button.set_active(render_settings.borrow().color_glyphs);
+        font_color_glyphs_button.connect_toggled(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).color_glyphs = btn.get_active();
+            drawing_area.queue_draw();
+        }));
+
+        let font_color_palette_spin_button: SpinButton = builder.get_object("font_color_palette").expect("Couldn't get font color palette spin button");
+        font_color_palette_spin_button.set_value(render_settings.borrow().color_palette as f64);
+        font_color_palette_spin_button.connect_value_changed(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).color_palette = btn.get_value() as usize;
+            drawing_area.queue_draw();
+        }));
+
+        let font_shape_text_button: CheckButton = builder.get_object("font_shape_text").expect("Couldn't get shape text button");
+        font_shape_text_button.set_active(render_settings.borrow().shape_text);
+        font_shape_text_button.connect_toggled(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).shape_text = btn.get_active();
+            drawing_area.queue_draw();
+        }));
+
+        let variations_expander: Expander = builder.get_object("variations").expect("Couldn't get variations expander");
+        let variation_instance_combo: ComboBoxText = builder.get_object("variation_instance").expect("Couldn't get variation instance combo box");
+        let variation_axes_box: gtk::Box = builder.get_object("variation_axes").expect("Couldn't get variation axes box");
+        let variations = Variations::from_face(&render_settings.borrow().library, &render_settings.borrow().face);
+        if let Some(variations) = variations {
+            let axis_spin_buttons: Vec<SpinButton> = variations.axes.iter().map(|axis| {
+                let row = gtk::Box::new(Orientation::Horizontal, 0);
+                let label = Label::new(Some(axis.name.as_str()));
+                let spin_button = SpinButton::new_with_range(axis.minimum, axis.maximum, 1.0);
+                spin_button.set_digits(1);
+                spin_button.set_value(axis.default);
+                spin_button.set_hexpand(true);
+                row.pack_start(&label, false, true, 0);
+                row.pack_start(&spin_button, false, true, 0);
+                variation_axes_box.pack_start(&row, false, true, 0);
+                spin_button
+            }).collect();
+            variation_axes_box.show_all();
+
+            let axis_spin_buttons = Rc::new(axis_spin_buttons);
+            for spin_button in axis_spin_buttons.iter() {
+                spin_button.connect_value_changed(clone!(drawing_area, render_settings, axis_spin_buttons => move |_| {
+                    let coordinates: Vec<f64> = axis_spin_buttons.iter().map(|b| b.get_value()).collect();
+                    variation::set_coordinates(&render_settings.borrow().face, &coordinates).expect("Variable font");
+                    (*render_settings.borrow_mut()).variation_coordinates = coordinates;
+                    drawing_area.queue_draw();
+                }));
+            }
+
+            variation_instance_combo.append_text("Default");
+            for instance in &variations.instances {
+                variation_instance_combo.append_text(&instance.name);
+            }
+            variation_instance_combo.set_active(0);
+            variation_instance_combo.connect_changed(clone!(axis_spin_buttons => move |combo| {
+                let active = combo.get_active();
+                let coordinates: Vec<f64> = if active > 0 {
+                    variations.instances[active as usize - 1].coordinates.clone()
+                } else {
+                    variations.axes.iter().map(|a| a.default).collect()
+                };
+                for (spin_button, value) in axis_spin_buttons.iter().zip(coordinates) {
+                    spin_button.set_value(value);
+                }
+            }));
+        } else {
+            variations_expander.set_sensitive(false);
+        }
+
+        let style_bold_spin_button: SpinButton = builder.get_object("style_bold").expect("Couldn't get style bold spin button");
+        style_bold_spin_button.set_value(render_settings.borrow().synthetic_bold);
+        style_bold_spin_button.connect_value_changed(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).synthetic_bold = btn.get_value();
+            drawing_area.queue_draw();
+        }));
+
+        let style_oblique_spin_button: SpinButton = builder.get_object("style_oblique").expect("Couldn't get style oblique spin button");
+        style_oblique_spin_button.set_value(render_settings.borrow().synthetic_oblique);
+        style_oblique_spin_button.connect_value_changed(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).synthetic_oblique = btn.get_value();
+            drawing_area.queue_draw();
+        }));
+
+        let style_stretch_spin_button: SpinButton = builder.get_object("style_stretch").expect("Couldn't get style stretch spin button");
+        style_stretch_spin_button.set_value(render_settings.borrow().stretch as f64);
+        style_stretch_spin_button.connect_value_changed(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).stretch = btn.get_value() as u32;
+            drawing_area.queue_draw();
+        }));
+
         let border_color_button: ColorButton = builder.get_object("border_color").expect("Couldn't get border color button");
         border_color_button.set_rgba(&render_settings.borrow().border_color);
         ColorButtonExt::connect_property_rgba_notify(&border_color_button, clone!(drawing_area, render_settings => move |btn| {
@@ -122,7 +371,245 @@ impl MainWindow {
             drawing_area.queue_draw();
         }));
 
-        drawing_area.connect_draw(clone!(drawing_area, render_settings => move |_, cr| {
+        let exporter_source_bpp_combo: ComboBoxText = builder.get_object("exporter_source_bpp").expect("Couldn't get source bits per pixel combo box");
+        exporter_source_bpp_combo.set_active_id(Some(render_settings.borrow().source_bits_per_pixel.to_string().as_str()));
+        exporter_source_bpp_combo.connect_changed(clone!(render_settings => move |combo| {
+            if let Some(bits) = combo.get_active_id().and_then(|id| id.parse().ok()) {
+                (*render_settings.borrow_mut()).source_bits_per_pixel = bits;
+            }
+        }));
+
+        let exporter_image_format_combo: ComboBoxText = builder.get_object("exporter_image_format").expect("Couldn't get image format combo box");
+        for format in ImageFormat::all() {
+            exporter_image_format_combo.append(Some(format.id()), format.name());
+        }
+        exporter_image_format_combo.set_active_id(Some(render_settings.borrow().image_format.id()));
+        exporter_image_format_combo.connect_changed(clone!(render_settings => move |combo| {
+            if let Some(format) = combo.get_active_id().and_then(|id| ImageFormat::from_id(&id)) {
+                (*render_settings.borrow_mut()).image_format = format;
+            }
+        }));
+
+        let exporter_pixel_format_combo: ComboBoxText = builder.get_object("exporter_pixel_format").expect("Couldn't get pixel format combo box");
+        for format in PixelFormat::all() {
+            exporter_pixel_format_combo.append(Some(format.id()), format.name());
+        }
+        exporter_pixel_format_combo.set_active_id(Some(render_settings.borrow().pixel_format.id()));
+        exporter_pixel_format_combo.connect_changed(clone!(render_settings => move |combo| {
+            if let Some(format) = combo.get_active_id().and_then(|id| PixelFormat::from_id(&id)) {
+                (*render_settings.borrow_mut()).pixel_format = format;
+            }
+        }));
+
+        let exporter_mip_levels_spin_button: SpinButton = builder.get_object("exporter_mip_levels").expect("Couldn't get mip levels spin button");
+        exporter_mip_levels_spin_button.set_value(render_settings.borrow().mip_levels as f64);
+        exporter_mip_levels_spin_button.connect_value_changed(clone!(drawing_area, render_settings => move |btn| {
+            let new_levels = btn.get_value() as u32;
+            (*render_settings.borrow_mut()).mip_levels = new_levels;
+            drawing_area.queue_draw();
+        }));
+
+        let exporter_extrude_combo: ComboBoxText = builder.get_object("exporter_extrude").expect("Couldn't get extrude combo box");
+        for extrude in Extrude::all() {
+            exporter_extrude_combo.append(Some(extrude.id()), extrude.name());
+        }
+        exporter_extrude_combo.set_active_id(Some(render_settings.borrow().extrude.id()));
+        exporter_extrude_combo.connect_changed(clone!(drawing_area, render_settings => move |combo| {
+            if let Some(extrude) = combo.get_active_id().and_then(|id| Extrude::from_id(&id)) {
+                (*render_settings.borrow_mut()).extrude = extrude;
+                drawing_area.queue_draw();
+            }
+        }));
+
+        let exporter_rotate_button: CheckButton = builder.get_object("exporter_rotate").expect("Couldn't get rotate button");
+        exporter_rotate_button.set_active(render_settings.borrow().rotate_glyphs);
+        exporter_rotate_button.connect_toggled(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).rotate_glyphs = btn.get_active();
+            drawing_area.queue_draw();
+        }));
+
+        let exporter_hiero_button: CheckButton = builder.get_object("exporter_hiero").expect("Couldn't get hiero button");
+        exporter_hiero_button.set_active(render_settings.borrow().hiero_settings);
+        exporter_hiero_button.connect_toggled(clone!(render_settings => move |btn| {
+            (*render_settings.borrow_mut()).hiero_settings = btn.get_active();
+        }));
+
+        let grid_enabled_button: CheckButton = builder.get_object("grid_enabled").expect("Couldn't get grid layout button");
+        grid_enabled_button.set_active(render_settings.borrow().grid);
+        grid_enabled_button.connect_toggled(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).grid = btn.get_active();
+            drawing_area.queue_draw();
+        }));
+
+        let grid_columns_spin_button: SpinButton = builder.get_object("grid_columns").expect("Couldn't get grid columns spin button");
+        grid_columns_spin_button.set_value(render_settings.borrow().grid_columns as f64);
+        grid_columns_spin_button.connect_value_changed(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).grid_columns = btn.get_value() as u32;
+            drawing_area.queue_draw();
+        }));
+
+        let grid_first_codepoint_spin_button: SpinButton = builder.get_object("grid_first_codepoint").expect("Couldn't get grid first codepoint spin button");
+        grid_first_codepoint_spin_button.set_value(render_settings.borrow().grid_first_codepoint as f64);
+        grid_first_codepoint_spin_button.connect_value_changed(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).grid_first_codepoint = btn.get_value() as u32;
+            drawing_area.queue_draw();
+        }));
+
+        let grid_cell_width_spin_button: SpinButton = builder.get_object("grid_cell_width").expect("Couldn't get grid cell width spin button");
+        grid_cell_width_spin_button.set_value(render_settings.borrow().grid_cell_width as f64);
+        grid_cell_width_spin_button.connect_value_changed(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).grid_cell_width = btn.get_value() as u32;
+            drawing_area.queue_draw();
+        }));
+
+        let grid_cell_height_spin_button: SpinButton = builder.get_object("grid_cell_height").expect("Couldn't get grid cell height spin button");
+        grid_cell_height_spin_button.set_value(render_settings.borrow().grid_cell_height as f64);
+        grid_cell_height_spin_button.connect_value_changed(clone!(drawing_area, render_settings => move |btn| {
+            (*render_settings.borrow_mut()).grid_cell_height = btn.get_value() as u32;
+            drawing_area.queue_draw();
+        }));
+
+        let preview_area: DrawingArea = builder.get_object("preview_area").expect("Couldn't get preview area");
+        let preview_text_entry: Entry = builder.get_object("preview_text").expect("Couldn't get preview text entry");
+        preview_text_entry.connect_changed(clone!(preview_area => move |_| {
+            preview_area.queue_draw();
+        }));
+        let preview_align_combo: ComboBoxText = builder.get_object("preview_align").expect("Couldn't get preview align combo box");
+        for align in Align::all() {
+            preview_align_combo.append(Some(align.id()), align.name());
+        }
+        preview_align_combo.set_active_id(Some(Align::Left.id()));
+        preview_align_combo.connect_changed(clone!(preview_area => move |_| {
+            preview_area.queue_draw();
+        }));
+
+        // Last rendered atlas, for finding the glyph under the pointer and the preview, and the
+        // descriptor of its first variant
+        let atlas: Rc<RefCell<Option<Atlas>>> = Rc::new(RefCell::new(None));
+        let preview_font_file: Rc<RefCell<Option<FontFile>>> = Rc::new(RefCell::new(None));
+
+        // Lays out the sample text from the descriptor of the atlas shown in the drawing area
+        preview_area.connect_draw(clone!(preview_area, preview_text_entry, preview_align_combo, atlas, preview_font_file => move |_, cr| {
+            let text = preview_text_entry.get_text().unwrap_or_default();
+            let preview_width = preview_area.get_allocated_width() - 16;
+            let mut layout = Layout::new();
+            layout.max_width = Some(preview_width);
+            layout.align = preview_align_combo.get_active_id().and_then(|id| Align::from_id(&id)).unwrap_or(Align::Left);
+
+            cr.set_source_rgb(0.5, 0.5, 0.5);
+            cr.paint();
+            if let (Some(atlas), Some(font_file)) = (atlas.borrow().as_ref(), preview_font_file.borrow().as_ref()) {
+                let pages: Vec<cairo::ImageSurface> = atlas.pages.iter().map(|page| page.to_surface()).collect();
+                cr.translate(8.0, 8.0);
+                preview::draw(cr, font_file, &pages, &layout, &text, preview_width as f64, (preview_area.get_allocated_height() - 16) as f64);
+            }
+
+            Inhibit(false)
+        }));
+
+        let selected_glyph: Rc<RefCell<Option<GlyphInfo>>> = Rc::new(RefCell::new(None));
+        // Last rendering error, so it is only reported once
+        let render_error: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+
+        let exporter_page_spin_button: SpinButton = builder.get_object("exporter_page").expect("Couldn't get exporter page spin button");
+        exporter_page_spin_button.connect_value_changed(clone!(drawing_area => move |_| {
+            drawing_area.queue_draw();
+        }));
+
+        let glyph_selected_label: Label = builder.get_object("glyph_selected").expect("Couldn't get selected glyph label");
+        let glyph_xoffset_spin_button: SpinButton = builder.get_object("glyph_xoffset").expect("Couldn't get glyph x offset spin button");
+        glyph_xoffset_spin_button.connect_value_changed(clone!(drawing_area, render_settings, selected_glyph => move |btn| {
+            if let Some(codepoint) = selected_glyph.borrow().as_ref().and_then(|glyph| glyph.codepoint) {
+                let mut adjustment = render_settings.borrow().glyph_adjustment(codepoint);
+                adjustment.xoffset = btn.get_value() as i32;
+                (*render_settings.borrow_mut()).set_glyph_adjustment(adjustment);
+                drawing_area.queue_draw();
+            }
+        }));
+
+        let glyph_yoffset_spin_button: SpinButton = builder.get_object("glyph_yoffset").expect("Couldn't get glyph y offset spin button");
+        glyph_yoffset_spin_button.connect_value_changed(clone!(drawing_area, render_settings, selected_glyph => move |btn| {
+            if let Some(codepoint) = selected_glyph.borrow().as_ref().and_then(|glyph| glyph.codepoint) {
+                let mut adjustment = render_settings.borrow().glyph_adjustment(codepoint);
+                adjustment.yoffset = btn.get_value() as i32;
+                (*render_settings.borrow_mut()).set_glyph_adjustment(adjustment);
+                drawing_area.queue_draw();
+            }
+        }));
+
+        let glyph_xadvance_spin_button: SpinButton = builder.get_object("glyph_xadvance").expect("Couldn't get glyph x advance spin button");
+        glyph_xadvance_spin_button.connect_value_changed(clone!(drawing_area, render_settings, selected_glyph => move |btn| {
+            if let Some(codepoint) = selected_glyph.borrow().as_ref().and_then(|glyph| glyph.codepoint) {
+                let mut adjustment = render_settings.borrow().glyph_adjustment(codepoint);
+                adjustment.xadvance = btn.get_value() as i32;
+                (*render_settings.borrow_mut()).set_glyph_adjustment(adjustment);
+                drawing_area.queue_draw();
+            }
+        }));
+
+        let glyph_kerning_second_entry: Entry = builder.get_object("glyph_kerning_second").expect("Couldn't get glyph kerning entry");
+        let glyph_kerning_amount_spin_button: SpinButton = builder.get_object("glyph_kerning_amount").expect("Couldn't get glyph kerning spin button");
+        glyph_kerning_second_entry.connect_changed(clone!(render_settings, selected_glyph, glyph_kerning_amount_spin_button => move |entry| {
+            let second = entry.get_text().and_then(|text| text.chars().next());
+            if let (Some(first), Some(second)) = (selected_glyph.borrow().as_ref().and_then(|glyph| glyph.codepoint), second) {
+                let amount = render_settings.borrow().kerning_adjustment(first, second as usize);
+                glyph_kerning_amount_spin_button.set_value(amount as f64);
+            }
+        }));
+        glyph_kerning_amount_spin_button.connect_value_changed(clone!(drawing_area, render_settings, selected_glyph, glyph_kerning_second_entry => move |btn| {
+            let second = glyph_kerning_second_entry.get_text().and_then(|text| text.chars().next());
+            if let (Some(first), Some(second)) = (selected_glyph.borrow().as_ref().and_then(|glyph| glyph.codepoint), second) {
+                (*render_settings.borrow_mut()).set_kerning_adjustment(first, second as usize, btn.get_value() as i32);
+                drawing_area.queue_draw();
+            }
+        }));
+
+        let glyph_adjustments_clear_button: Button = builder.get_object("glyph_adjustments_clear").expect("Couldn't get glyph adjustments clear button");
+        glyph_adjustments_clear_button.connect_clicked(clone!(drawing_area, render_settings, glyph_xoffset_spin_button, glyph_yoffset_spin_button, glyph_xadvance_spin_button, glyph_kerning_amount_spin_button => move |_| {
+            (*render_settings.borrow_mut()).glyph_adjustments.clear();
+            (*render_settings.borrow_mut()).kerning_adjustments.clear();
+            glyph_xoffset_spin_button.set_value(0.0);
+            glyph_yoffset_spin_button.set_value(0.0);
+            glyph_xadvance_spin_button.set_value(0.0);
+            glyph_kerning_amount_spin_button.set_value(0.0);
+            drawing_area.queue_draw();
+        }));
+
+        let glyph_index_label: Label = builder.get_object("glyph_index").expect("Couldn't get glyph index label");
+        let glyph_rect_label: Label = builder.get_object("glyph_rect").expect("Couldn't get glyph rect label");
+        let glyph_offset_label: Label = builder.get_object("glyph_offset").expect("Couldn't get glyph offset label");
+        let glyph_advance_label: Label = builder.get_object("glyph_advance").expect("Couldn't get glyph advance label");
+        let glyph_bearing_label: Label = builder.get_object("glyph_bearing").expect("Couldn't get glyph bearing label");
+        let glyph_overlays_check_button: CheckButton = builder.get_object("glyph_overlays").expect("Couldn't get glyph overlays check button");
+        glyph_overlays_check_button.connect_toggled(clone!(drawing_area => move |_| {
+            drawing_area.queue_draw();
+        }));
+
+        drawing_area.connect_button_press_event(clone!(drawing_area, atlas, selected_glyph, exporter_page_spin_button, render_settings, glyph_xoffset_spin_button, glyph_yoffset_spin_button, glyph_xadvance_spin_button, glyph_kerning_second_entry, glyph_kerning_amount_spin_button => move |_, event| {
+            let (x, y) = event.get_position();
+            let glyph = atlas.borrow().as_ref()
+                .and_then(|atlas| atlas.glyph_at(exporter_page_spin_button.get_value_as_int() as u32, x as u32, y as u32))
+                .cloned();
+
+            if let Some(glyph) = glyph {
+                // Cleared first, so filling in the values doesn't change the previous glyph
+                *selected_glyph.borrow_mut() = None;
+                if let Some(codepoint) = glyph.codepoint {
+                    let adjustment = render_settings.borrow().glyph_adjustment(codepoint);
+                    glyph_xoffset_spin_button.set_value(adjustment.xoffset as f64);
+                    glyph_yoffset_spin_button.set_value(adjustment.yoffset as f64);
+                    glyph_xadvance_spin_button.set_value(adjustment.xadvance as f64);
+                    let second = glyph_kerning_second_entry.get_text().and_then(|text| text.chars().next());
+                    let amount = second.map(|second| render_settings.borrow().kerning_adjustment(codepoint, second as usize)).unwrap_or(0);
+                    glyph_kerning_amount_spin_button.set_value(amount as f64);
+                }
+                *selected_glyph.borrow_mut() = Some(glyph);
+                drawing_area.queue_draw();
+            }
+            Inhibit(false)
+        }));
+
+        drawing_area.connect_draw(clone!(window, drawing_area, preview_area, preview_font_file, render_error, render_settings, atlas, selected_glyph, exporter_page_spin_button, glyph_overlays_check_button, glyph_selected_label, glyph_index_label, glyph_rect_label, glyph_offset_label, glyph_advance_label, glyph_bearing_label => move |_, cr| {
             let width = drawing_area.get_allocated_width();
             let height = drawing_area.get_allocated_height();
 
@@ -151,12 +638,66 @@ impl MainWindow {
             {
                 let render_settings = render_settings.borrow();
                 let glyphs = Glyphs::new();
-                let (_,surface) = glyphs.render_to_surface(&render_settings, width, height);
+                let rendered_atlas = match glyphs.render_to_surface(&render_settings, width, height) {
+                    Ok(rendered_atlas) => rendered_atlas,
+                    Err(err) => {
+                        *atlas.borrow_mut() = None;
+                        *preview_font_file.borrow_mut() = None;
+                        let message = err.to_string();
+                        if render_error.borrow().as_ref() != Some(&message) {
+                            // Dialogs can't run inside a draw handler
+                            gtk::idle_add(clone!(window, message => move || {
+                                let dialog = MessageDialog::new(Some(&window), DialogFlags::MODAL, MessageType::Error, ButtonsType::Ok,
+                                    &format!("Couldn't render the atlas:\n{}", message));
+                                dialog.run();
+                                dialog.destroy();
+                                Continue(false)
+                            }));
+                            *render_error.borrow_mut() = Some(message);
+                        }
+                        preview_area.queue_draw();
+                        return Inhibit(false);
+                    },
+                };
+                *render_error.borrow_mut() = None;
+
+                // Clamps the page when fewer are needed, which draws again
+                exporter_page_spin_button.set_range(0.0, (rendered_atlas.pages.len() - 1) as f64);
+                let page = exporter_page_spin_button.get_value_as_int() as u32;
 
                 cr.set_operator(cairo::Operator::Over);
-                cr.set_source_surface(&surface, 0.0, 0.0);
+                cr.set_source_surface(&rendered_atlas.pages[page as usize].to_surface(), 0.0, 0.0);
                 cr.paint();
+
+                let padding = render_settings.letter_padding as u32;
+                if glyph_overlays_check_button.get_active() {
+                    inspector::draw_overlays(cr, &rendered_atlas, page, padding);
+                }
+
+                // The glyph moves around as settings change, so it is looked up again in the new atlas
+                let selected = selected_glyph.borrow().as_ref().and_then(|glyph| rendered_atlas.same_glyph(glyph).cloned());
+                if let Some(ref glyph) = selected {
+                    if glyph.page == page && glyph.width > 0 {
+                        inspector::draw_selection(cr, glyph);
+                    }
+
+                    let description = inspector::Description::new(glyph, &rendered_atlas, &render_settings);
+                    glyph_selected_label.set_text(&description.glyph);
+                    glyph_index_label.set_text(&description.glyph_index);
+                    glyph_rect_label.set_text(&description.rect);
+                    glyph_offset_label.set_text(&description.offset);
+                    glyph_advance_label.set_text(&description.advance);
+                    glyph_bearing_label.set_text(&description.bearing);
+                }
+                if selected.is_some() {
+                    *selected_glyph.borrow_mut() = selected;
+                }
+
+                let page_files: Vec<String> = (0..rendered_atlas.pages.len()).map(|id| id.to_string()).collect();
+                *preview_font_file.borrow_mut() = glyphs.font_files(&rendered_atlas, &render_settings, width, height, &page_files).into_iter().next();
+                *atlas.borrow_mut() = Some(rendered_atlas);
             }
+            preview_area.queue_draw();
 
             Inhibit(false)
         }));
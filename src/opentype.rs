@@ -0,0 +1,440 @@
+//! Pair kerning read from the `GPOS` table, which most current fonts use instead of the legacy
+//! `kern` table FreeType reads, and the alternate glyphs of single substitutions in the `GSUB`
+//! table. Only pair adjustments of the `kern` feature and single substitutions are read,
+//! features of all scripts and languages alike.
+//! For more info see the [GPOS][1], [GSUB][2] and [common table format][3] specifications.
+//!
+//! [1]: https://docs.microsoft.com/en-us/typography/opentype/spec/gpos
+//! [2]: https://docs.microsoft.com/en-us/typography/opentype/spec/gsub
+//! [3]: https://docs.microsoft.com/en-us/typography/opentype/spec/chapter2
+//!
+
+use ft;
+use std::collections::{HashMap};
+use sfnt::{self, read_u16, read_u32};
+
+/// GPOS lookup types
+const PAIR_ADJUSTMENT: u16 = 2;
+const GPOS_EXTENSION: u16 = 9;
+
+/// GSUB lookup types
+const SINGLE_SUBSTITUTION: u16 = 1;
+const GSUB_EXTENSION: u16 = 7;
+
+/// Value record format flag of the horizontal advance adjustment
+const X_ADVANCE: u16 = 0x0004;
+
+/// Pair adjustment lookups of the `kern` feature of a face.
+pub struct PairKerning {
+    gpos: Vec<u8>,
+    /// Offsets of the pair adjustment subtables of each lookup, in lookup list order
+    lookups: Vec<Vec<usize>>,
+}
+
+impl PairKerning {
+    /// Reads the lookups, returns `None` if the face has no GPOS kerning.
+    pub fn from_face(face: &ft::Face) -> Option<PairKerning> {
+        PairKerning::from_table(sfnt::load_table(face, sfnt::tag(b"GPOS"))?)
+    }
+
+    fn from_table(gpos: Vec<u8>) -> Option<PairKerning> {
+        let lookups: Vec<Vec<usize>> = feature_lookups(&gpos, &[b"kern"]).unwrap_or_default().into_iter()
+            .filter_map(|lookup| lookup_subtables(&gpos, lookup, PAIR_ADJUSTMENT, GPOS_EXTENSION))
+            .filter(|subtables| !subtables.is_empty())
+            .collect();
+        if lookups.is_empty() {
+            return None;
+        }
+
+        Some(PairKerning {
+            gpos: gpos,
+            lookups: lookups,
+        })
+    }
+
+    /// Adjustment of the advance of the left glyph in font units. Each lookup applies its first
+    /// subtable that covers the pair.
+    pub fn get(&self, left: u32, right: u32) -> i32 {
+        self.lookups.iter()
+            .filter_map(|subtables| subtables.iter().filter_map(|&subtable| pair_adjustment(&self.gpos, subtable, left, right)).next())
+            .sum()
+    }
+}
+
+/// Alternate glyph the single substitutions of the features with the given tags replace each
+/// glyph with. Lookups apply in lookup list order, each to the result of the ones before.
+pub fn alternates<T: AsRef<[u8]>>(face: &ft::Face, features: &[T]) -> HashMap<u32, u32> {
+    if features.is_empty() {
+        return HashMap::new();
+    }
+    sfnt::load_table(face, sfnt::tag(b"GSUB"))
+        .map(|gsub| alternates_in_table(&gsub, features))
+        .unwrap_or_default()
+}
+
+fn alternates_in_table<T: AsRef<[u8]>>(gsub: &[u8], features: &[T]) -> HashMap<u32, u32> {
+    let mut alternates: HashMap<u32, u32> = HashMap::new();
+
+    for lookup in feature_lookups(gsub, features).unwrap_or_default() {
+        // A glyph takes the substitute of the first subtable that covers it
+        let mut substitutes: HashMap<u32, u32> = HashMap::new();
+        for subtable in lookup_subtables(gsub, lookup, SINGLE_SUBSTITUTION, GSUB_EXTENSION).unwrap_or_default() {
+            for (glyph, substitute) in single_substitutes(gsub, subtable).unwrap_or_default() {
+                substitutes.entry(glyph).or_insert(substitute);
+            }
+        }
+
+        for alternate in alternates.values_mut() {
+            if let Some(&substitute) = substitutes.get(alternate) {
+                *alternate = substitute;
+            }
+        }
+        for (glyph, substitute) in substitutes {
+            alternates.entry(glyph).or_insert(substitute);
+        }
+    }
+
+    alternates
+}
+
+/// Each glyph a single substitution subtable covers and its substitute.
+fn single_substitutes(gsub: &[u8], subtable: usize) -> Option<Vec<(u32, u32)>> {
+    let format = read_u16(gsub, subtable)?;
+    let glyphs = covered_glyphs(gsub, subtable + read_u16(gsub, subtable + 2)? as usize)?;
+
+    match format {
+        1 => {
+            let delta = read_u16(gsub, subtable + 4)?;
+            Some(glyphs.into_iter().map(|glyph| (glyph, (glyph as u16).wrapping_add(delta) as u32)).collect())
+        },
+        2 => {
+            let count = read_u16(gsub, subtable + 4)? as usize;
+            glyphs.into_iter().take(count).enumerate()
+                .map(|(index, glyph)| Some((glyph, read_u16(gsub, subtable + 6 + index * 2)? as u32)))
+                .collect()
+        },
+        _ => None,
+    }
+}
+
+/// Indices of the lookups of the features with the given tags in a GSUB or GPOS table, sorted
+/// and without duplicates.
+fn feature_lookups<T: AsRef<[u8]>>(table: &[u8], tags: &[T]) -> Option<Vec<u16>> {
+    let feature_list = read_u16(table, 6)? as usize;
+    let feature_count = read_u16(table, feature_list)? as usize;
+    let mut lookups = Vec::new();
+
+    for i in 0..feature_count {
+        let record = feature_list + 2 + i * 6;
+        let tag = table.get(record..record + 4)?;
+        if !tags.iter().any(|t| t.as_ref() == tag) {
+            continue;
+        }
+
+        let feature = feature_list + read_u16(table, record + 4)? as usize;
+        let lookup_count = read_u16(table, feature + 2)? as usize;
+        for j in 0..lookup_count {
+            lookups.push(read_u16(table, feature + 4 + j * 2)?);
+        }
+    }
+
+    lookups.sort();
+    lookups.dedup();
+    Some(lookups)
+}
+
+/// Offsets of the subtables of a lookup that are of `lookup_type`, directly or wrapped in an
+/// extension subtable.
+fn lookup_subtables(table: &[u8], lookup_index: u16, lookup_type: u16, extension_type: u16) -> Option<Vec<usize>> {
+    let lookup_list = read_u16(table, 8)? as usize;
+    if lookup_index >= read_u16(table, lookup_list)? {
+        return None;
+    }
+    let lookup = lookup_list + read_u16(table, lookup_list + 2 + lookup_index as usize * 2)? as usize;
+    let kind = read_u16(table, lookup)?;
+    let subtable_count = read_u16(table, lookup + 4)? as usize;
+    let mut subtables = Vec::new();
+
+    for i in 0..subtable_count {
+        let subtable = lookup + read_u16(table, lookup + 6 + i * 2)? as usize;
+        if kind == lookup_type {
+            subtables.push(subtable);
+        } else if kind == extension_type && read_u16(table, subtable + 2)? == lookup_type {
+            subtables.push(subtable + read_u32(table, subtable + 4)? as usize);
+        }
+    }
+
+    Some(subtables)
+}
+
+/// Advance adjustment of the left glyph by a pair adjustment subtable, `None` if the subtable
+/// doesn't cover the pair.
+fn pair_adjustment(gpos: &[u8], subtable: usize, left: u32, right: u32) -> Option<i32> {
+    let format = read_u16(gpos, subtable)?;
+    let coverage_index = coverage_index(gpos, subtable + read_u16(gpos, subtable + 2)? as usize, left)?;
+    let value_format1 = read_u16(gpos, subtable + 4)?;
+    let value_format2 = read_u16(gpos, subtable + 6)?;
+    let record_size = value_record_size(value_format1) + value_record_size(value_format2);
+
+    match format {
+        1 => {
+            let pair_set_count = read_u16(gpos, subtable + 8)? as usize;
+            if coverage_index >= pair_set_count {
+                return None;
+            }
+            let pair_set = subtable + read_u16(gpos, subtable + 10 + coverage_index * 2)? as usize;
+            let pair_count = read_u16(gpos, pair_set)? as usize;
+            let index = find_glyph(gpos, pair_set + 2, pair_count, 2 + record_size, right)?;
+            x_advance(gpos, pair_set + 2 + index * (2 + record_size) + 2, value_format1)
+        },
+        2 => {
+            let class1 = class(gpos, subtable + read_u16(gpos, subtable + 8)? as usize, left) as usize;
+            let class2 = class(gpos, subtable + read_u16(gpos, subtable + 10)? as usize, right) as usize;
+            let class1_count = read_u16(gpos, subtable + 12)? as usize;
+            let class2_count = read_u16(gpos, subtable + 14)? as usize;
+            if class1 >= class1_count || class2 >= class2_count {
+                return None;
+            }
+            x_advance(gpos, subtable + 16 + (class1 * class2_count + class2) * record_size, value_format1)
+        },
+        _ => None,
+    }
+}
+
+/// Size of a value record in bytes, each field the format flags is 16 bits wide.
+fn value_record_size(value_format: u16) -> usize {
+    2 * (value_format & 0xff).count_ones() as usize
+}
+
+/// The horizontal advance adjustment of a value record, 0 if the format has none.
+fn x_advance(table: &[u8], record: usize, value_format: u16) -> Option<i32> {
+    if value_format & X_ADVANCE == 0 {
+        return Some(0);
+    }
+    // The placement fields come before the advance
+    let offset = value_record_size(value_format & (X_ADVANCE - 1));
+    read_u16(table, record + offset).map(|value| value as i16 as i32)
+}
+
+/// Index of the glyph in a coverage table, `None` if the table doesn't cover it.
+fn coverage_index(table: &[u8], coverage: usize, glyph: u32) -> Option<usize> {
+    match read_u16(table, coverage)? {
+        1 => find_glyph(table, coverage + 4, read_u16(table, coverage + 2)? as usize, 2, glyph),
+        2 => {
+            let index = find_range(table, coverage + 4, read_u16(table, coverage + 2)? as usize, 6, glyph)?;
+            let record = coverage + 4 + index * 6;
+            Some(read_u16(table, record + 4)? as usize + (glyph - read_u16(table, record)? as u32) as usize)
+        },
+        _ => None,
+    }
+}
+
+/// All glyphs of a coverage table in the order of their coverage index.
+fn covered_glyphs(table: &[u8], coverage: usize) -> Option<Vec<u32>> {
+    let count = read_u16(table, coverage + 2)? as usize;
+    let mut glyphs = Vec::new();
+
+    match read_u16(table, coverage)? {
+        1 => {
+            for i in 0..count {
+                glyphs.push(read_u16(table, coverage + 4 + i * 2)? as u32);
+            }
+        },
+        2 => {
+            for i in 0..count {
+                let record = coverage + 4 + i * 6;
+                let start = read_u16(table, record)? as u32;
+                let end = read_u16(table, record + 2)? as u32;
+                glyphs.extend(start..end + 1);
+            }
+        },
+        _ => return None,
+    }
+
+    Some(glyphs)
+}
+
+/// Class of the glyph in a class definition table, 0 for glyphs it doesn't list.
+fn class(table: &[u8], class_def: usize, glyph: u32) -> u16 {
+    let class = match read_u16(table, class_def) {
+        Some(1) => read_u16(table, class_def + 2).and_then(|start| {
+            let count = read_u16(table, class_def + 4)? as u32;
+            if glyph >= start as u32 && glyph - (start as u32) < count {
+                read_u16(table, class_def + 6 + (glyph - start as u32) as usize * 2)
+            } else {
+                None
+            }
+        }),
+        Some(2) => read_u16(table, class_def + 2).and_then(|count| {
+            let index = find_range(table, class_def + 4, count as usize, 6, glyph)?;
+            read_u16(table, class_def + 4 + index * 6 + 4)
+        }),
+        _ => None,
+    };
+    class.unwrap_or(0)
+}
+
+/// Index of the record starting with the glyph, in an array of `count` records of `size` bytes
+/// sorted by glyph.
+fn find_glyph(table: &[u8], array: usize, count: usize, size: usize, glyph: u32) -> Option<usize> {
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = (low + high) / 2;
+        let first = read_u16(table, array + middle * size)? as u32;
+        if glyph < first {
+            high = middle;
+        } else if glyph > first {
+            low = middle + 1;
+        } else {
+            return Some(middle);
+        }
+    }
+    None
+}
+
+/// Index of the range record containing the glyph, in an array of `count` records of `size`
+/// bytes that start with the first and last glyph of their range.
+fn find_range(table: &[u8], array: usize, count: usize, size: usize, glyph: u32) -> Option<usize> {
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = (low + high) / 2;
+        let start = read_u16(table, array + middle * size)? as u32;
+        let end = read_u16(table, array + middle * size + 2)? as u32;
+        if glyph < start {
+            high = middle;
+        } else if glyph > end {
+            low = middle + 1;
+        } else {
+            return Some(middle);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_u16(table: &mut Vec<u8>, values: &[u16]) {
+        for value in values {
+            table.push((value >> 8) as u8);
+            table.push(*value as u8);
+        }
+    }
+
+    /// A GSUB or GPOS table with one feature that uses all lookups, each of one subtable.
+    fn table(feature: &[u8; 4], lookups: &[(u16, &[u16])]) -> Vec<u8> {
+        let count = lookups.len() as u16;
+        let mut table = Vec::new();
+        // Header: version 1.0, script list, feature list and lookup list
+        push_u16(&mut table, &[1, 0, 0, 10, 22 + count * 2]);
+        // Feature list at 10 with the feature at 8 from it
+        push_u16(&mut table, &[1]);
+        table.extend_from_slice(feature);
+        push_u16(&mut table, &[8, 0, count]);
+        push_u16(&mut table, &(0..count).collect::<Vec<u16>>());
+        // Lookup list with the subtable of each lookup at 8 from the lookup
+        push_u16(&mut table, &[count]);
+        let mut offset = 2 + count * 2;
+        for &(_, subtable) in lookups {
+            push_u16(&mut table, &[offset]);
+            offset += 8 + subtable.len() as u16 * 2;
+        }
+        for &(lookup_type, subtable) in lookups {
+            push_u16(&mut table, &[lookup_type, 0, 1, 8]);
+            push_u16(&mut table, subtable);
+        }
+        table
+    }
+
+    /// A GPOS table with a `kern` feature of one lookup that holds `subtable`.
+    fn gpos(lookup_type: u16, subtable: &[u16]) -> Vec<u8> {
+        table(b"kern", &[(lookup_type, subtable)])
+    }
+
+    #[test]
+    fn reads_pair_adjustments_of_glyph_pairs() {
+        // Format 1, coverage at 16, XAdvance of the first glyph, no value of the second and
+        // one pair set at 22 for glyph 5: (5, 7) = -40 and (5, 9) = 25
+        let kerning = PairKerning::from_table(gpos(PAIR_ADJUSTMENT, &[
+            1, 16, X_ADVANCE, 0, 1, 22,
+            0, 0,
+            1, 1, 5,
+            2, 7, (-40i16) as u16, 9, 25,
+        ])).unwrap();
+
+        assert_eq!(kerning.get(5, 7), -40);
+        assert_eq!(kerning.get(5, 9), 25);
+        assert_eq!(kerning.get(5, 8), 0);
+        assert_eq!(kerning.get(7, 5), 0);
+    }
+
+    #[test]
+    fn reads_pair_adjustments_of_glyph_classes() {
+        // Format 2 with an XPlacement before the XAdvance, coverage at 32 for glyphs 10 to 12,
+        // glyphs 10 to 12 in class 1 on the left and glyph 20 in class 1 on the right
+        let kerning = PairKerning::from_table(gpos(PAIR_ADJUSTMENT, &[
+            2, 32, 0x0001 | X_ADVANCE, 0, 42, 54, 2, 2,
+            0, 0, 0, 0, 0, 0, 3, (-60i16) as u16,
+            2, 1, 10, 12, 0,
+            1, 10, 3, 1, 1, 1,
+            1, 20, 1, 1,
+        ])).unwrap();
+
+        assert_eq!(kerning.get(11, 20), -60);
+        assert_eq!(kerning.get(11, 21), 0);
+        assert_eq!(kerning.get(13, 20), 0);
+    }
+
+    #[test]
+    fn follows_extension_subtables() {
+        // Extension of a pair adjustment at 8 from it, format 1 with coverage of glyph 1 and
+        // the pair (1, 2) = -10
+        let kerning = PairKerning::from_table(gpos(GPOS_EXTENSION, &[
+            1, PAIR_ADJUSTMENT, 0, 8,
+            1, 16, X_ADVANCE, 0, 1, 22,
+            0, 0,
+            1, 1, 1,
+            1, 2, (-10i16) as u16,
+        ])).unwrap();
+
+        assert_eq!(kerning.get(1, 2), -10);
+    }
+
+    #[test]
+    fn ignores_tables_without_kern_feature() {
+        let mut table = gpos(PAIR_ADJUSTMENT, &[]);
+        table[12..16].copy_from_slice(b"mark");
+        assert!(PairKerning::from_table(table).is_none());
+    }
+
+    #[test]
+    fn applies_single_substitutions_in_lookup_order() {
+        // Format 1 adding 100 to glyphs 17 and 18, then format 2 replacing glyph 30 with 31 and
+        // glyph 117 with 200
+        let gsub = table(b"tnum", &[
+            (SINGLE_SUBSTITUTION, &[1, 6, 100, 1, 2, 17, 18]),
+            (SINGLE_SUBSTITUTION, &[2, 10, 2, 31, 200, 1, 2, 30, 117]),
+        ]);
+        let alternates = alternates_in_table(&gsub, &["tnum"]);
+
+        assert_eq!(alternates.len(), 4);
+        assert_eq!(alternates.get(&17), Some(&200));
+        assert_eq!(alternates.get(&18), Some(&118));
+        assert_eq!(alternates.get(&30), Some(&31));
+        assert_eq!(alternates.get(&117), Some(&200));
+        assert!(alternates_in_table(&gsub, &["onum"]).is_empty());
+    }
+
+    #[test]
+    fn reads_single_substitutions_of_glyph_ranges_in_extensions() {
+        // Extension of a format 1 substitution adding 5 to glyphs 40 to 42
+        let gsub = table(b"smcp", &[
+            (GSUB_EXTENSION, &[1, SINGLE_SUBSTITUTION, 0, 8, 1, 6, 5, 2, 1, 40, 42, 0]),
+        ]);
+        let alternates = alternates_in_table(&gsub, &["smcp"]);
+
+        assert_eq!(alternates.len(), 3);
+        assert_eq!(alternates.get(&41), Some(&46));
+    }
+}
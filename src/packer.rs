@@ -0,0 +1,170 @@
+use std::cmp;
+
+/// Places rectangles row by row onto pages of a fixed size. A row is wrapped when the next
+/// rectangle doesn't fit horizontally and a new page is started when the next row doesn't fit
+/// vertically.
+pub struct Packer {
+    width: i32,
+    height: i32,
+    spacing: i32,
+    /// Rectangle positions are multiples of it
+    alignment: i32,
+    /// Tall rectangles are turned on their side
+    rotation: bool,
+    page: u32,
+    left: i32,
+    top: i32,
+    row_height: i32,
+}
+
+impl Packer {
+    pub fn new(width: i32, height: i32, spacing: i32) -> Packer {
+        Packer {
+            width: width,
+            height: height,
+            spacing: spacing,
+            alignment: 1,
+            rotation: false,
+            page: 0,
+            left: 0,
+            top: 0,
+            row_height: 0,
+        }
+    }
+
+    /// Aligns rectangles to multiples of `2^levels` and separates them by at least as much, so
+    /// they cover distinct texels with a one texel gap down to mip level `levels`.
+    pub fn align_for_mip_levels(&mut self, levels: u32) {
+        self.alignment = 1 << levels;
+        self.spacing = cmp::max(self.spacing, self.alignment);
+    }
+
+    /// Allows `pack_rotatable` to turn rectangles by 90 degrees. Rows only get as high as their
+    /// tallest rectangle, so laying tall rectangles flat wastes less space above the others.
+    pub fn set_rotation(&mut self, enabled: bool) {
+        self.rotation = enabled;
+    }
+
+    /// Continues packing at the beginning of the next row.
+    pub fn new_row(&mut self) {
+        if self.left > 0 {
+            self.top = self.align(self.top + self.row_height + self.spacing);
+            self.left = 0;
+            self.row_height = 0;
+        }
+    }
+
+    /// Returns page and position of the top-left corner for a rectangle of the given size.
+    /// Rectangles larger than a page are placed at the start of a fresh page and get clipped.
+    pub fn pack(&mut self, width: i32, height: i32) -> (u32, i32, i32) {
+        if self.left > 0 && self.left + width > self.width {
+            self.new_row();
+        }
+
+        if self.top > 0 && self.top + height > self.height {
+            self.page += 1;
+            self.left = 0;
+            self.top = 0;
+            self.row_height = 0;
+        }
+
+        let position = (self.page, self.left, self.top);
+
+        self.left = self.align(self.left + width + self.spacing);
+        self.row_height = cmp::max(self.row_height, height);
+
+        position
+    }
+
+    /// Like `pack`, but places the rectangle with width and height swapped if rotation is
+    /// enabled and it is taller than wide. The last value tells whether it was rotated.
+    pub fn pack_rotatable(&mut self, width: i32, height: i32) -> (u32, i32, i32, bool) {
+        if self.rotation && height > width {
+            let (page, left, top) = self.pack(height, width);
+            (page, left, top, true)
+        } else {
+            let (page, left, top) = self.pack(width, height);
+            (page, left, top, false)
+        }
+    }
+
+    fn align(&self, value: i32) -> i32 {
+        (value + self.alignment - 1) / self.alignment * self.alignment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_rows_with_spacing() {
+        let mut packer = Packer::new(20, 20, 2);
+        assert_eq!(packer.pack(5, 4), (0, 0, 0));
+        assert_eq!(packer.pack(5, 6), (0, 7, 0));
+        assert_eq!(packer.pack(6, 3), (0, 14, 0));
+    }
+
+    #[test]
+    fn wraps_rows_below_the_tallest_rectangle() {
+        let mut packer = Packer::new(20, 20, 2);
+        packer.pack(8, 4);
+        packer.pack(8, 6);
+        assert_eq!(packer.pack(8, 3), (0, 0, 8));
+    }
+
+    #[test]
+    fn new_row_only_wraps_started_rows() {
+        let mut packer = Packer::new(20, 20, 1);
+        packer.new_row();
+        assert_eq!(packer.pack(4, 4), (0, 0, 0));
+        packer.new_row();
+        packer.new_row();
+        assert_eq!(packer.pack(4, 4), (0, 0, 5));
+    }
+
+    #[test]
+    fn starts_new_pages() {
+        let mut packer = Packer::new(10, 10, 0);
+        assert_eq!(packer.pack(10, 6), (0, 0, 0));
+        assert_eq!(packer.pack(10, 6), (1, 0, 0));
+        assert_eq!(packer.pack(10, 4), (1, 0, 6));
+    }
+
+    #[test]
+    fn places_oversized_rectangles_on_fresh_pages() {
+        let mut packer = Packer::new(10, 10, 0);
+        assert_eq!(packer.pack(4, 4), (0, 0, 0));
+        assert_eq!(packer.pack(20, 20), (1, 0, 0));
+        assert_eq!(packer.pack(4, 4), (2, 0, 0));
+    }
+
+    #[test]
+    fn aligns_for_mip_levels() {
+        let mut packer = Packer::new(64, 64, 1);
+        packer.align_for_mip_levels(2);
+        assert_eq!(packer.pack(5, 3), (0, 0, 0));
+        assert_eq!(packer.pack(3, 3), (0, 12, 0));
+        packer.new_row();
+        assert_eq!(packer.pack(3, 3), (0, 0, 8));
+    }
+
+    #[test]
+    fn keeps_larger_spacing_for_mip_levels() {
+        let mut packer = Packer::new(64, 64, 6);
+        packer.align_for_mip_levels(1);
+        packer.pack(3, 3);
+        assert_eq!(packer.pack(3, 3), (0, 10, 0));
+    }
+
+    #[test]
+    fn rotates_tall_rectangles_when_enabled() {
+        let mut packer = Packer::new(64, 64, 0);
+        assert_eq!(packer.pack_rotatable(4, 10), (0, 0, 0, false));
+        packer.set_rotation(true);
+        assert_eq!(packer.pack_rotatable(4, 10), (0, 4, 0, true));
+        assert_eq!(packer.pack_rotatable(6, 2), (0, 14, 0, false));
+        packer.new_row();
+        assert_eq!(packer.pack_rotatable(4, 4), (0, 0, 10, false));
+    }
+}
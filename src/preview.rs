@@ -0,0 +1,57 @@
+//! Draws sample text the way a game would, using nothing but a font descriptor and its pages, so
+//! errors in the exported metrics show up before the files ship.
+
+use cairo::{Context, ImageSurface};
+use std::f64;
+
+use font::{FontFile};
+use layout::{Layout, Quad};
+
+/// Draws the text laid out by `layout` with its top-left corner at the origin of the context, on
+/// top of guides for the top of each line in blue and its baseline in red that reach `width`
+/// and `height`.
+pub fn draw(cr: &Context, font_file: &FontFile, pages: &[ImageSurface], layout: &Layout, text: &str, width: f64, height: f64) {
+    let line_height = font_file.common.line_height as i32;
+    let base = font_file.common.base;
+
+    cr.set_line_width(1.0);
+    let mut top = 0;
+    while (top as f64) < height && line_height > 0 {
+        guide(cr, top, width, (0.3, 0.5, 1.0));
+        guide(cr, top + base, width, (1.0, 0.3, 0.3));
+        top += line_height;
+    }
+
+    for quad in layout.quads(font_file, text) {
+        if let Some(page) = pages.get(quad.page as usize) {
+            draw_quad(cr, &quad, page);
+        }
+    }
+}
+
+fn guide(cr: &Context, y: i32, width: f64, (red, green, blue): (f64, f64, f64)) {
+    cr.set_source_rgb(red, green, blue);
+    cr.move_to(0.0, y as f64 + 0.5);
+    cr.line_to(width, y as f64 + 0.5);
+    cr.stroke();
+}
+
+/// Copies the source rect of the quad to its destination, turning rotated glyphs back upright.
+fn draw_quad(cr: &Context, quad: &Quad, page: &ImageSurface) {
+    let (x, y, width, height) = quad.source;
+    let (left, top, _, _) = quad.destination;
+
+    cr.save();
+    cr.translate(left as f64, top as f64);
+    if quad.rotated {
+        // The source rect holds the glyph turned clockwise
+        cr.rotate(-f64::consts::FRAC_PI_2);
+        cr.translate(-((x + width) as f64), -(y as f64));
+    } else {
+        cr.translate(-(x as f64), -(y as f64));
+    }
+    cr.rectangle(x as f64, y as f64, width as f64, height as f64);
+    cr.set_source_surface(page, 0.0, 0.0);
+    cr.fill();
+    cr.restore();
+}
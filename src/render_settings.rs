@@ -1,17 +1,100 @@
 use ft;
 use gdk::{RGBA};
+use image::{Extrude, ImageFormat, PixelFormat};
+use image_glyph::{ImageGlyph};
+use shaping::{Feature};
 use std::rc::{Rc};
+use variation::{self, Variations};
+
+/// A face rendered at a specific size into the shared atlas pages.
+pub struct FontVariant {
+    pub face: Rc<ft::Face<'static>>,
+    pub font_size: isize,
+}
+
+/// Change of the exported metrics of the glyph of a codepoint, in pixels.
+#[derive(Clone, Copy, PartialEq)]
+pub struct GlyphAdjustment {
+    pub codepoint: usize,
+    pub xoffset: i32,
+    pub yoffset: i32,
+    pub xadvance: i32,
+}
+
+/// Kerning added to that of the font for a pair of codepoints, in pixels.
+#[derive(Clone, Copy)]
+pub struct KerningAdjustment {
+    pub first: usize,
+    pub second: usize,
+    pub amount: i32,
+}
 
 pub struct RenderSettings {
     pub library: Rc<ft::Library>,
     pub face: Rc<ft::Face<'static>>,
     pub border_color: RGBA,
     pub border_width: isize,
+    /// Render COLR layered glyphs and color bitmap strikes in color instead of the font color
+    pub color_glyphs: bool,
+    /// CPAL palette used for COLR layered glyphs
+    pub color_palette: usize,
+    /// How glyph pixels are copied into the transparent pixels around them
+    pub extrude: Extrude,
+    /// Additional faces packed into the same atlas pages as `face`
+    pub extra_faces: Vec<Rc<ft::Face<'static>>>,
+    /// Additional sizes packed into the same atlas pages as `font_size`
+    pub extra_sizes: Vec<isize>,
+    /// Faces tried in order for codepoints `face` doesn't contain
+    pub fallback_faces: Vec<Rc<ft::Face<'static>>>,
+    /// OpenType features whose alternate glyphs are rendered for the codepoints, like tabular
+    /// figures
+    pub features: Vec<Feature>,
     pub font_color: RGBA,
     pub font_size: isize,
+    /// Metric changes of single glyphs, at most one for each codepoint
+    pub glyph_adjustments: Vec<GlyphAdjustment>,
+    /// Glyphs of the primary face to add by their index, like alternates no codepoint maps to
+    pub glyph_indices: Vec<u32>,
+    /// Place every glyph in an identical cell in codepoint order instead of packing the rects.
+    /// Letter spacing, rotation and mip level alignment don't apply to grids.
+    pub grid: bool,
+    /// Cell height of the grid, 0 uses the line height
+    pub grid_cell_height: u32,
+    /// Cell width of the grid, 0 uses the widest advance
+    pub grid_cell_width: u32,
+    /// Cells in each row of the grid, fewer if they don't fit the page
+    pub grid_columns: u32,
+    /// Codepoint of the first cell of the grid
+    pub grid_first_codepoint: u32,
+    /// Write a `.hiero` settings file for libGDX next to `.fnt` descriptors
+    pub hiero_settings: bool,
+    /// File format of the atlas pages
+    pub image_format: ImageFormat,
+    /// Images packed as the glyphs of their codepoints, in place of glyphs of the fonts
+    pub image_glyphs: Vec<ImageGlyph>,
+    /// Kerning changes of pairs, at most one for each pair
+    pub kerning_adjustments: Vec<KerningAdjustment>,
     pub letter_padding: isize,
     pub letter_spacing: isize,
+    /// Mip levels the glyph rects stay separate in, 0 disables mipmaps
+    pub mip_levels: u32,
+    /// Channels of the atlas pages for image formats other than PNG
+    pub pixel_format: PixelFormat,
+    /// Allow turning glyphs by 90 degrees clockwise when that packs them tighter
+    pub rotate_glyphs: bool,
+    /// Shape the text with HarfBuzz and add the ligatures and contextual forms it maps to
+    pub shape_text: bool,
+    /// Bits per pixel of the atlas pages embedded by the source code exporters, 1, 2, 4 or 8
+    pub source_bits_per_pixel: u32,
+    /// Horizontal stretch in percent applied to the outlines
+    pub stretch: u32,
+    /// Outline emboldening in pixels for faces without a bold style, 0 disables it
+    pub synthetic_bold: f64,
+    /// Oblique shear in degrees for faces without an italic style, 0 disables it
+    pub synthetic_oblique: f64,
     pub text: String,
+    /// Design coordinates of the variable font instance of `face`, empty for the default instance
+    pub variation_coordinates: Vec<f64>,
 }
 
 impl RenderSettings {
@@ -25,22 +108,150 @@ impl RenderSettings {
             face: face.clone(),
             border_color: RGBA::black(),
             border_width: 4,
+            color_glyphs: true,
+            color_palette: 0,
+            extrude: Extrude::None,
+            extra_faces: Vec::new(),
+            extra_sizes: Vec::new(),
+            fallback_faces: Vec::new(),
+            features: Vec::new(),
             font_color: RGBA::white(),
             font_size: default_font_size,
+            glyph_adjustments: Vec::new(),
+            glyph_indices: Vec::new(),
+            grid: false,
+            grid_cell_height: 0,
+            grid_cell_width: 0,
+            grid_columns: 16,
+            grid_first_codepoint: 32,
+            hiero_settings: false,
+            image_format: ImageFormat::Png,
+            image_glyphs: Vec::new(),
+            kerning_adjustments: Vec::new(),
             letter_padding: 0,
             letter_spacing: 2,
+            mip_levels: 0,
+            pixel_format: PixelFormat::Rgba8,
+            rotate_glyphs: false,
+            shape_text: false,
+            source_bits_per_pixel: 8,
+            stretch: 100,
+            synthetic_bold: 0.0,
+            synthetic_oblique: 0.0,
             text: String::from("AaBbCcDd"),
+            variation_coordinates: Vec::new(),
         }
     }
 
     pub fn reset(&mut self) {
         self.border_color = RGBA::black();
         self.border_width = 4;
+        self.color_glyphs = true;
+        self.color_palette = 0;
+        self.extrude = Extrude::None;
+        self.extra_faces.clear();
+        self.extra_sizes.clear();
+        self.fallback_faces.clear();
+        self.features.clear();
         self.font_color = RGBA::white();
         self.font_size = 128;
         self.face.set_char_size(0, self.font_size*64, 0, 64).unwrap();
+        self.glyph_adjustments.clear();
+        self.glyph_indices.clear();
+        self.grid = false;
+        self.grid_cell_height = 0;
+        self.grid_cell_width = 0;
+        self.grid_columns = 16;
+        self.grid_first_codepoint = 32;
+        self.hiero_settings = false;
+        self.image_format = ImageFormat::Png;
+        self.image_glyphs.clear();
+        self.kerning_adjustments.clear();
         self.letter_padding = 0;
         self.letter_spacing = 2;
+        self.mip_levels = 0;
+        self.pixel_format = PixelFormat::Rgba8;
+        self.rotate_glyphs = false;
+        self.shape_text = false;
+        self.source_bits_per_pixel = 8;
+        self.stretch = 100;
+        self.synthetic_bold = 0.0;
+        self.synthetic_oblique = 0.0;
         self.text = String::from("AaBbCcDd");
+        self.variation_coordinates.clear();
+        variation::set_coordinates(&self.face, &[]).ok();
+    }
+
+    /// Name of the face written to the font files, for variable fonts including the instance.
+    pub fn face_name(&self, face: &Rc<ft::Face<'static>>) -> String {
+        let family = face.family_name().unwrap_or_default();
+        if !Rc::ptr_eq(face, &self.face) {
+            return family;
+        }
+
+        match Variations::from_face(&self.library, face) {
+            Some(variations) => format!("{} {}", family, variations.describe(&self.variation_coordinates)),
+            None => family,
+        }
+    }
+
+    /// Metric changes of the glyph of the codepoint, all 0 if it has none.
+    pub fn glyph_adjustment(&self, codepoint: usize) -> GlyphAdjustment {
+        self.glyph_adjustments.iter().find(|a| a.codepoint == codepoint).cloned().unwrap_or(GlyphAdjustment {
+            codepoint: codepoint,
+            xoffset: 0,
+            yoffset: 0,
+            xadvance: 0,
+        })
     }
-}
\ No newline at end of file
+
+    /// Replaces the metric changes of the glyph of `adjustment.codepoint`, changes of 0 remove it.
+    pub fn set_glyph_adjustment(&mut self, adjustment: GlyphAdjustment) {
+        self.glyph_adjustments.retain(|a| a.codepoint != adjustment.codepoint);
+        if adjustment != self.glyph_adjustment(adjustment.codepoint) {
+            self.glyph_adjustments.push(adjustment);
+        }
+    }
+
+    /// Kerning change of the pair, 0 if it has none.
+    pub fn kerning_adjustment(&self, first: usize, second: usize) -> i32 {
+        self.kerning_adjustments.iter()
+            .find(|k| k.first == first && k.second == second)
+            .map(|k| k.amount)
+            .unwrap_or(0)
+    }
+
+    /// Replaces the kerning change of the pair, an amount of 0 removes it.
+    pub fn set_kerning_adjustment(&mut self, first: usize, second: usize, amount: i32) {
+        self.kerning_adjustments.retain(|k| k.first != first || k.second != second);
+        if amount != 0 {
+            self.kerning_adjustments.push(KerningAdjustment {
+                first: first,
+                second: second,
+                amount: amount,
+            });
+        }
+    }
+
+    /// Every face and size combination that gets packed into the atlas, the primary face at
+    /// `font_size` always comes first.
+    pub fn variants(&self) -> Vec<FontVariant> {
+        let mut sizes = vec![self.font_size];
+        for &size in &self.extra_sizes {
+            if !sizes.contains(&size) {
+                sizes.push(size);
+            }
+        }
+
+        let mut variants = Vec::new();
+        for face in Some(&self.face).into_iter().chain(self.extra_faces.iter()) {
+            for &size in &sizes {
+                variants.push(FontVariant {
+                    face: face.clone(),
+                    font_size: size,
+                });
+            }
+        }
+        variants
+    }
+}
@@ -0,0 +1,55 @@
+//! Access to raw OpenType tables of a face, for data FreeType doesn't interpret itself.
+//! All values in the tables are stored big-endian.
+//!
+
+use ft;
+use ft::freetype_sys as fts;
+use std::ptr;
+
+extern "C" {
+    fn FT_Load_Sfnt_Table(face: fts::FT_Face, tag: fts::FT_ULong, offset: fts::FT_Long, buffer: *mut fts::FT_Byte, length: *mut fts::FT_ULong) -> fts::FT_Error;
+}
+
+/// Builds a table tag from its four letter name, e.g. `tag(b"COLR")`.
+pub fn tag(name: &[u8; 4]) -> fts::FT_ULong {
+    (name[0] as fts::FT_ULong) << 24 | (name[1] as fts::FT_ULong) << 16 | (name[2] as fts::FT_ULong) << 8 | name[3] as fts::FT_ULong
+}
+
+/// Returns the contents of the table, or `None` if the face doesn't have it.
+pub fn load_table(face: &ft::Face, tag: fts::FT_ULong) -> Option<Vec<u8>> {
+    let raw_face = face.raw() as *const fts::FT_FaceRec as fts::FT_Face;
+    let mut length: fts::FT_ULong = 0;
+
+    let err = unsafe {
+        FT_Load_Sfnt_Table(raw_face, tag, 0, ptr::null_mut(), &mut length)
+    };
+    if err != fts::FT_Err_Ok || length == 0 {
+        return None;
+    }
+
+    let mut buffer = vec![0u8; length as usize];
+    let err = unsafe {
+        FT_Load_Sfnt_Table(raw_face, tag, 0, buffer.as_mut_ptr(), &mut length)
+    };
+    if err != fts::FT_Err_Ok {
+        return None;
+    }
+
+    Some(buffer)
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    if offset + 2 <= data.len() {
+        Some((data[offset] as u16) << 8 | data[offset + 1] as u16)
+    } else {
+        None
+    }
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    if offset + 4 <= data.len() {
+        Some((data[offset] as u32) << 24 | (data[offset + 1] as u32) << 16 | (data[offset + 2] as u32) << 8 | data[offset + 3] as u32)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,215 @@
+//! Text shaping with HarfBuzz, which applies the OpenType substitutions of a face to map text to
+//! glyphs, including the contextual forms and ligatures of scripts like Arabic, Devanagari or
+//! Thai that a codepoint to glyph mapping can't produce.
+//! For more info see the [HarfBuzz manual][1].
+//!
+//! [1]: https://harfbuzz.github.io/
+//!
+
+use ft;
+use ft::freetype_sys as fts;
+use std::collections::{BTreeMap};
+use std::os::raw::{c_char, c_int, c_uint};
+use std::slice;
+
+#[allow(non_camel_case_types)]
+enum hb_font_t {}
+
+#[allow(non_camel_case_types)]
+enum hb_buffer_t {}
+
+#[repr(C)]
+#[allow(dead_code, non_camel_case_types)]
+struct hb_glyph_info_t {
+    codepoint: u32,
+    mask: u32,
+    cluster: u32,
+    var1: u32,
+    var2: u32,
+}
+
+#[repr(C)]
+#[allow(dead_code, non_camel_case_types)]
+struct hb_glyph_position_t {
+    x_advance: i32,
+    y_advance: i32,
+    x_offset: i32,
+    y_offset: i32,
+    var: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct hb_feature_t {
+    tag: u32,
+    value: u32,
+    start: c_uint,
+    end: c_uint,
+}
+
+// Linked by build.rs, which finds HarfBuzz with pkg-config
+extern "C" {
+    fn hb_ft_font_create_referenced(ft_face: fts::FT_Face) -> *mut hb_font_t;
+    fn hb_font_destroy(font: *mut hb_font_t);
+    fn hb_buffer_create() -> *mut hb_buffer_t;
+    fn hb_buffer_destroy(buffer: *mut hb_buffer_t);
+    fn hb_buffer_add_utf8(buffer: *mut hb_buffer_t, text: *const c_char, text_length: c_int, item_offset: c_uint, item_length: c_int);
+    fn hb_buffer_guess_segment_properties(buffer: *mut hb_buffer_t);
+    fn hb_shape(font: *mut hb_font_t, buffer: *mut hb_buffer_t, features: *const hb_feature_t, num_features: c_uint);
+    fn hb_buffer_get_glyph_infos(buffer: *mut hb_buffer_t, length: *mut c_uint) -> *mut hb_glyph_info_t;
+    fn hb_buffer_get_glyph_positions(buffer: *mut hb_buffer_t, length: *mut c_uint) -> *mut hb_glyph_position_t;
+}
+
+/// OpenType features that replace glyphs with alternates.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Feature {
+    TabularFigures,
+    LiningFigures,
+    OldstyleFigures,
+    SmallCaps,
+    SlashedZero,
+    /// Stylistic set 1 to 20
+    StylisticSet(u8),
+}
+
+impl Feature {
+    pub fn all() -> Vec<Feature> {
+        let mut features = vec![
+            Feature::TabularFigures,
+            Feature::LiningFigures,
+            Feature::OldstyleFigures,
+            Feature::SmallCaps,
+            Feature::SlashedZero,
+        ];
+        features.extend((1..21).map(Feature::StylisticSet));
+        features
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            Feature::TabularFigures => "Tabular Figures".to_string(),
+            Feature::LiningFigures => "Lining Figures".to_string(),
+            Feature::OldstyleFigures => "Oldstyle Figures".to_string(),
+            Feature::SmallCaps => "Small Capitals".to_string(),
+            Feature::SlashedZero => "Slashed Zero".to_string(),
+            Feature::StylisticSet(set) => format!("Stylistic Set {}", set),
+        }
+    }
+
+    /// The OpenType feature tag
+    pub fn id(&self) -> String {
+        match *self {
+            Feature::TabularFigures => "tnum".to_string(),
+            Feature::LiningFigures => "lnum".to_string(),
+            Feature::OldstyleFigures => "onum".to_string(),
+            Feature::SmallCaps => "smcp".to_string(),
+            Feature::SlashedZero => "zero".to_string(),
+            Feature::StylisticSet(set) => format!("ss{:02}", set),
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Feature> {
+        Feature::all().into_iter().find(|feature| feature.id() == id)
+    }
+
+    fn to_hb(&self) -> hb_feature_t {
+        let tag = self.id().bytes().fold(0, |tag, byte| tag << 8 | byte as u32);
+        hb_feature_t {
+            tag: tag,
+            value: 1,
+            start: 0,
+            end: c_uint::max_value(),
+        }
+    }
+}
+
+/// Where HarfBuzz places a glyph, in pixels with y pointing down.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Position {
+    /// Offset of the glyph from the text cursor
+    pub offset: (i32, i32),
+    /// Distance to move the text cursor forward after the glyph
+    pub advance: (i32, i32),
+}
+
+/// The smallest run of characters HarfBuzz maps to a run of glyphs.
+pub struct Cluster {
+    pub text: String,
+    /// The characters of the line right before and after the cluster, they decide contextual
+    /// forms like the initial, medial or final form of an Arabic letter
+    pub before: Option<char>,
+    pub after: Option<char>,
+    /// Glyph indices in the face, 0 where the face has no glyph for a character
+    pub glyphs: Vec<u32>,
+    /// Position of each glyph
+    pub positions: Vec<Position>,
+}
+
+/// Shapes each line of the text with the face and features, guessing script, language and
+/// direction, and returns the clusters in the order of the text. Positions need the char size
+/// of the face to be set.
+pub fn shape(face: &ft::Face, text: &str, features: &[Feature]) -> Vec<Cluster> {
+    let mut clusters = Vec::new();
+
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        // Glyphs of each cluster by the byte offset of its first character in the line
+        let mut glyphs: BTreeMap<usize, Vec<(u32, Position)>> = BTreeMap::new();
+        for (cluster, glyph, position) in shape_line(face, line, features) {
+            glyphs.entry(cluster).or_insert_with(Vec::new).push((glyph, position));
+        }
+
+        let starts: Vec<usize> = glyphs.keys().cloned().collect();
+        for (index, (&start, glyphs)) in glyphs.iter().enumerate() {
+            let end = starts.get(index + 1).cloned().unwrap_or(line.len());
+            clusters.push(Cluster {
+                text: line[start..end].to_string(),
+                before: line[..start].chars().next_back(),
+                after: line[end..].chars().next(),
+                glyphs: glyphs.iter().map(|&(glyph, _)| glyph).collect(),
+                positions: glyphs.iter().map(|&(_, position)| position).collect(),
+            });
+        }
+    }
+
+    clusters
+}
+
+/// Returns the cluster, glyph index and position of each glyph HarfBuzz shapes the line to.
+fn shape_line(face: &ft::Face, line: &str, features: &[Feature]) -> Vec<(usize, u32, Position)> {
+    let raw_face = face.raw() as *const fts::FT_FaceRec as fts::FT_Face;
+    let features: Vec<hb_feature_t> = features.iter().map(Feature::to_hb).collect();
+    let mut glyphs = Vec::new();
+
+    unsafe {
+        let font = hb_ft_font_create_referenced(raw_face);
+        let buffer = hb_buffer_create();
+        hb_buffer_add_utf8(buffer, line.as_ptr() as *const c_char, line.len() as c_int, 0, line.len() as c_int);
+        hb_buffer_guess_segment_properties(buffer);
+        hb_shape(font, buffer, features.as_ptr(), features.len() as c_uint);
+
+        let mut length: c_uint = 0;
+        let infos = hb_buffer_get_glyph_infos(buffer, &mut length);
+        let positions = hb_buffer_get_glyph_positions(buffer, &mut length);
+        if !infos.is_null() && !positions.is_null() {
+            let infos = slice::from_raw_parts(infos, length as usize);
+            let positions = slice::from_raw_parts(positions, length as usize);
+            for (info, position) in infos.iter().zip(positions) {
+                glyphs.push((info.cluster as usize, info.codepoint, Position {
+                    offset: (pixels(position.x_offset), -pixels(position.y_offset)),
+                    advance: (pixels(position.x_advance), -pixels(position.y_advance)),
+                }));
+            }
+        }
+
+        hb_buffer_destroy(buffer);
+        hb_font_destroy(font);
+    }
+
+    glyphs
+}
+
+/// Rounds a HarfBuzz position of a font created from a FreeType face, in 26.6 fixed point, to
+/// pixels.
+fn pixels(value: i32) -> i32 {
+    (value as f64 / 64.0).round() as i32
+}
@@ -0,0 +1,268 @@
+//! Module for writing the atlas as source code, for targets that embed the font in the binary.
+//! Writes a C header or a Rust module with the pages, a glyph table sorted by codepoint, the
+//! kerning pairs and lookup functions.
+//!
+//! Pages only keep the coverage, i.e. the alpha channel, with 1, 2, 4 or 8 bits per pixel. The
+//! leftmost pixel of a byte is stored in the most significant bits and every row starts on a new
+//! byte.
+//!
+
+use std::io;
+
+use font::{CharTag, FontFile, KerningTag};
+use image::{Image};
+
+/// Coverage of the atlas pages packed with a fixed number of bits per pixel.
+pub struct Pages {
+    pub bits_per_pixel: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per row
+    pub stride: u32,
+    pub data: Vec<Vec<u8>>,
+}
+
+impl Pages {
+    pub fn from_images(images: &[Image], bits_per_pixel: u32) -> Pages {
+        let width = images.first().map(|i| i.width).unwrap_or(0);
+        let height = images.first().map(|i| i.height).unwrap_or(0);
+        let stride = (width * bits_per_pixel + 7) / 8;
+
+        let data = images.iter().map(|image| {
+            let mut packed = vec![0u8; (stride * height) as usize];
+
+            for y in 0..height as usize {
+                for x in 0..width as usize {
+                    let alpha = image.pixels[(y * width as usize + x) * 4 + 3];
+                    let value = alpha >> (8 - bits_per_pixel);
+                    let bit = x * bits_per_pixel as usize;
+                    let shift = 8 - bits_per_pixel as usize - bit % 8;
+                    packed[y * stride as usize + bit / 8] |= value << shift;
+                }
+            }
+            packed
+        }).collect();
+
+        Pages {
+            bits_per_pixel: bits_per_pixel,
+            width: width,
+            height: height,
+            stride: stride,
+            data: data,
+        }
+    }
+}
+
+/// Turns a file name into an identifier, e.g. `vt323-32` into `vt323_32`.
+pub fn identifier(name: &str) -> String {
+    let mut identifier: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect();
+    if identifier.chars().next().map_or(true, |c| c.is_ascii_digit()) {
+        identifier.insert(0, '_');
+    }
+    identifier
+}
+
+pub fn write_c(font_file: &FontFile, pages: &Pages, name: &str, write: &mut io::Write) -> io::Result<()> {
+    let prefix = name.to_uppercase();
+    let chars = sorted_chars(font_file);
+    let kernings = sorted_kernings(font_file);
+
+    write!(write, "/* Generated by font-atlas: {} */\n", description(font_file, pages))?;
+    write!(write, "#ifndef {}_H\n#define {}_H\n\n", prefix, prefix)?;
+    write!(write, "#include <stddef.h>\n#include <stdint.h>\n\n")?;
+
+    write!(write, "#define {}_BITS_PER_PIXEL {}\n", prefix, pages.bits_per_pixel)?;
+    write!(write, "#define {}_PAGE_WIDTH {}\n", prefix, pages.width)?;
+    write!(write, "#define {}_PAGE_HEIGHT {}\n", prefix, pages.height)?;
+    write!(write, "#define {}_PAGE_STRIDE {}\n", prefix, pages.stride)?;
+    write!(write, "#define {}_LINE_HEIGHT {}\n", prefix, font_file.common.line_height)?;
+    write!(write, "#define {}_BASE {}\n", prefix, font_file.common.base)?;
+    write!(write, "#define {}_GLYPH_COUNT {}\n", prefix, chars.len())?;
+    write!(write, "#define {}_KERNING_COUNT {}\n\n", prefix, kernings.len())?;
+
+    write!(write, "typedef struct {{\n    uint32_t codepoint;\n    uint16_t x;\n    uint16_t y;\n    uint16_t width;\n    uint16_t height;\n")?;
+    write!(write, "    int16_t xoffset;\n    int16_t yoffset;\n    int16_t xadvance;\n    uint8_t page;\n")?;
+    write!(write, "    /* Turned 90 degrees clockwise, covering height columns and width rows */\n    uint8_t rotated;\n}} {}_glyph_t;\n\n", name)?;
+    write!(write, "typedef struct {{\n    uint32_t first;\n    uint32_t second;\n    int16_t amount;\n}} {}_kerning_t;\n\n", name)?;
+
+    for (index, data) in pages.data.iter().enumerate() {
+        write!(write, "static const uint8_t {}_page_{}[{}] = {{\n", name, index, data.len())?;
+        write_bytes(write, data)?;
+        write!(write, "}};\n\n")?;
+    }
+    let page_names: Vec<String> = (0..pages.data.len()).map(|i| format!("{}_page_{}", name, i)).collect();
+    write!(write, "static const uint8_t *const {}_pages[{}] = {{ {} }};\n\n", name, page_names.len(), page_names.join(", "))?;
+
+    // C has no empty arrays, the counts tell how many entries are valid
+    write!(write, "static const {}_glyph_t {}_glyphs[] = {{\n", name, name)?;
+    for char in &chars {
+        write!(write, "    {{ {}, {}, {}, {}, {}, {}, {}, {}, {}, {} }},\n", char.id, char.x, char.y, char.width, char.height, char.xoffset, char.yoffset, char.xadvance, char.page, char.rotated as u8)?;
+    }
+    if chars.is_empty() {
+        write!(write, "    {{ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 }},\n")?;
+    }
+    write!(write, "}};\n\n")?;
+
+    write!(write, "static const {}_kerning_t {}_kernings[] = {{\n", name, name)?;
+    for kerning in &kernings {
+        write!(write, "    {{ {}, {}, {} }},\n", kerning.first, kerning.second, kerning.amount)?;
+    }
+    if kernings.is_empty() {
+        write!(write, "    {{ 0, 0, 0 }},\n")?;
+    }
+    write!(write, "}};\n\n")?;
+
+    write!(write, "static inline const {}_glyph_t *{}_find_glyph(uint32_t codepoint) {{\n", name, name)?;
+    write!(write, "    size_t low = 0, high = {}_GLYPH_COUNT;\n", prefix)?;
+    write!(write, "    while (low < high) {{\n        size_t mid = low + (high - low) / 2;\n")?;
+    write!(write, "        if ({}_glyphs[mid].codepoint < codepoint) low = mid + 1;\n", name)?;
+    write!(write, "        else if ({}_glyphs[mid].codepoint > codepoint) high = mid;\n", name)?;
+    write!(write, "        else return &{}_glyphs[mid];\n    }}\n    return NULL;\n}}\n\n", name)?;
+
+    write!(write, "static inline int16_t {}_find_kerning(uint32_t first, uint32_t second) {{\n", name)?;
+    write!(write, "    size_t low = 0, high = {}_KERNING_COUNT;\n", prefix)?;
+    write!(write, "    while (low < high) {{\n        size_t mid = low + (high - low) / 2;\n        const {}_kerning_t *k = &{}_kernings[mid];\n", name, name)?;
+    write!(write, "        if (k->first < first || (k->first == first && k->second < second)) low = mid + 1;\n")?;
+    write!(write, "        else if (k->first > first || k->second > second) high = mid;\n")?;
+    write!(write, "        else return k->amount;\n    }}\n    return 0;\n}}\n\n")?;
+
+    write!(write, "/* Coverage of a pixel scaled to 0-255 */\n")?;
+    write!(write, "static inline uint8_t {}_coverage(uint8_t page, uint16_t x, uint16_t y) {{\n", name)?;
+    write!(write, "    uint32_t bit = (uint32_t)x * {}_BITS_PER_PIXEL;\n", prefix)?;
+    write!(write, "    uint8_t byte = {}_pages[page][(uint32_t)y * {}_PAGE_STRIDE + bit / 8];\n", name, prefix)?;
+    write!(write, "    uint8_t max = (1u << {}_BITS_PER_PIXEL) - 1;\n", prefix)?;
+    write!(write, "    uint8_t value = (byte >> (8 - {}_BITS_PER_PIXEL - bit % 8)) & max;\n", prefix)?;
+    write!(write, "    return (uint8_t)(value * 255 / max);\n}}\n\n")?;
+
+    write!(write, "#endif\n")
+}
+
+pub fn write_rust(font_file: &FontFile, pages: &Pages, write: &mut io::Write) -> io::Result<()> {
+    let chars = sorted_chars(font_file);
+    let kernings = sorted_kernings(font_file);
+
+    write!(write, "//! Generated by font-atlas: {}\n\n", description(font_file, pages))?;
+
+    write!(write, "pub const BITS_PER_PIXEL: u32 = {};\n", pages.bits_per_pixel)?;
+    write!(write, "pub const PAGE_WIDTH: u32 = {};\n", pages.width)?;
+    write!(write, "pub const PAGE_HEIGHT: u32 = {};\n", pages.height)?;
+    write!(write, "pub const PAGE_STRIDE: usize = {};\n", pages.stride)?;
+    write!(write, "pub const LINE_HEIGHT: u32 = {};\n", font_file.common.line_height)?;
+    write!(write, "pub const BASE: i32 = {};\n\n", font_file.common.base)?;
+
+    write!(write, "#[derive(Clone, Copy, Debug, PartialEq, Eq)]\npub struct Glyph {{\n    pub codepoint: u32,\n    pub x: u16,\n    pub y: u16,\n    pub width: u16,\n    pub height: u16,\n")?;
+    write!(write, "    pub xoffset: i16,\n    pub yoffset: i16,\n    pub xadvance: i16,\n    pub page: u8,\n")?;
+    write!(write, "    /// Turned 90 degrees clockwise, covering `height` columns and `width` rows\n    pub rotated: bool,\n}}\n\n")?;
+
+    for (index, data) in pages.data.iter().enumerate() {
+        write!(write, "static PAGE_{}: [u8; {}] = [\n", index, data.len())?;
+        write_bytes(write, data)?;
+        write!(write, "];\n\n")?;
+    }
+    let page_names: Vec<String> = (0..pages.data.len()).map(|i| format!("&PAGE_{}", i)).collect();
+    write!(write, "pub static PAGES: [&[u8]; {}] = [{}];\n\n", page_names.len(), page_names.join(", "))?;
+
+    write!(write, "/// Sorted by codepoint\npub static GLYPHS: [Glyph; {}] = [\n", chars.len())?;
+    for char in &chars {
+        write!(write, "    Glyph {{ codepoint: {}, x: {}, y: {}, width: {}, height: {}, xoffset: {}, yoffset: {}, xadvance: {}, page: {}, rotated: {} }},\n",
+            char.id, char.x, char.y, char.width, char.height, char.xoffset, char.yoffset, char.xadvance, char.page, char.rotated)?;
+    }
+    write!(write, "];\n\n")?;
+
+    write!(write, "/// First and second codepoint and amount, sorted by the pair\npub static KERNINGS: [(u32, u32, i16); {}] = [\n", kernings.len())?;
+    for kerning in &kernings {
+        write!(write, "    ({}, {}, {}),\n", kerning.first, kerning.second, kerning.amount)?;
+    }
+    write!(write, "];\n\n")?;
+
+    write!(write, "pub fn glyph(codepoint: u32) -> Option<&'static Glyph> {{\n")?;
+    write!(write, "    GLYPHS.binary_search_by_key(&codepoint, |g| g.codepoint).ok().map(|i| &GLYPHS[i])\n}}\n\n")?;
+
+    write!(write, "pub fn kerning(first: u32, second: u32) -> i16 {{\n")?;
+    write!(write, "    KERNINGS.binary_search_by_key(&(first, second), |k| (k.0, k.1)).map(|i| KERNINGS[i].2).unwrap_or(0)\n}}\n\n")?;
+
+    write!(write, "/// Coverage of a pixel scaled to 0-255\npub fn coverage(page: usize, x: u32, y: u32) -> u8 {{\n")?;
+    write!(write, "    let bit = (x * BITS_PER_PIXEL) as usize;\n")?;
+    write!(write, "    let byte = PAGES[page][y as usize * PAGE_STRIDE + bit / 8];\n")?;
+    write!(write, "    let max = ((1u32 << BITS_PER_PIXEL) - 1) as u8;\n")?;
+    write!(write, "    let value = (byte >> (8 - BITS_PER_PIXEL as usize - bit % 8)) & max;\n")?;
+    write!(write, "    (value as u32 * 255 / max as u32) as u8\n}}\n")
+}
+
+fn description(font_file: &FontFile, pages: &Pages) -> String {
+    format!("{} {}px, {} bits per pixel coverage", font_file.info.face, font_file.info.size, pages.bits_per_pixel)
+}
+
+fn write_bytes(write: &mut io::Write, data: &[u8]) -> io::Result<()> {
+    for line in data.chunks(16) {
+        let bytes: Vec<String> = line.iter().map(|b| format!("0x{:02x}", b)).collect();
+        write!(write, "    {},\n", bytes.join(", "))?;
+    }
+    Ok(())
+}
+
+/// Chars sorted by codepoint without duplicates, as the lookup functions search them
+fn sorted_chars(font_file: &FontFile) -> Vec<&CharTag> {
+    let mut chars: Vec<&CharTag> = font_file.chars.iter().collect();
+    chars.sort_by_key(|c| c.id);
+    chars.dedup_by_key(|c| c.id);
+    chars
+}
+
+fn sorted_kernings(font_file: &FontFile) -> Vec<&KerningTag> {
+    let mut kernings: Vec<&KerningTag> = font_file.kernings.iter().collect();
+    kernings.sort_by_key(|k| (k.first, k.second));
+    kernings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One row with the given alphas
+    fn image(alphas: &[u8]) -> Image {
+        Image {
+            width: alphas.len() as u32,
+            height: 1,
+            pixels: alphas.iter().flat_map(|&a| vec![255, 255, 255, a]).collect(),
+        }
+    }
+
+    #[test]
+    fn packs_one_bit_per_pixel_msb_first() {
+        let pages = Pages::from_images(&[image(&[255, 0, 0, 128, 127, 0, 0, 0, 255])], 1);
+        assert_eq!(pages.stride, 2);
+        assert_eq!(pages.data, vec![vec![0b1001_0000, 0b1000_0000]]);
+    }
+
+    #[test]
+    fn packs_two_bits_per_pixel_msb_first() {
+        let pages = Pages::from_images(&[image(&[255, 0, 128, 64, 192])], 2);
+        assert_eq!(pages.stride, 2);
+        assert_eq!(pages.data, vec![vec![0b11_00_10_01, 0b11_00_00_00]]);
+    }
+
+    #[test]
+    fn packs_four_bits_per_pixel_msb_first() {
+        let pages = Pages::from_images(&[image(&[0xf0, 0x1f, 0x80])], 4);
+        assert_eq!(pages.stride, 2);
+        assert_eq!(pages.data, vec![vec![0xf1, 0x80]]);
+    }
+
+    #[test]
+    fn starts_rows_on_new_bytes() {
+        let mut two_rows = image(&[255, 255, 255]);
+        two_rows.height = 2;
+        two_rows.width = 3;
+        two_rows.pixels.extend(image(&[0, 0, 255]).pixels);
+        let pages = Pages::from_images(&[two_rows], 1);
+        assert_eq!(pages.stride, 1);
+        assert_eq!(pages.data, vec![vec![0b1110_0000, 0b0010_0000]]);
+    }
+
+    #[test]
+    fn makes_identifiers() {
+        assert_eq!(identifier("vt323-32"), "vt323_32");
+        assert_eq!(identifier("32px Font"), "_32px_font");
+    }
+}
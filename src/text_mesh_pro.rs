@@ -0,0 +1,255 @@
+//! Module for writing a TextMeshPro font asset as JSON, using the field names of Unity's
+//! serialized `TMP_FontAsset` so an editor script can copy the values into a font asset.
+//! For more info see the [font asset documentation][1].
+//!
+//! Unlike BMFont, glyph rects exclude the padding, which is stored once as `m_AtlasPadding`, and
+//! their y coordinate is measured from the bottom of the atlas. Characters sharing a rect and
+//! their offsets and advance share a single glyph, empty ones like spaces always get their own.
+//!
+//! [1]: https://docs.unity3d.com/Packages/com.unity.textmeshpro@3.0/manual/FontAssetsProperties.html
+//!
+
+use std::io;
+
+use font::{CharTag, FontFile};
+use json::{JsonWriter};
+
+/// `GlyphRenderMode.SMOOTH_HINTED`, anti-aliased bitmaps rendered with hinting
+const RENDER_MODE_SMOOTH_HINTED: i64 = 4121;
+
+pub fn write(font_file: &FontFile, write: &mut io::Write) -> io::Result<()> {
+    let mut out = JsonWriter::new(write);
+    let info = &font_file.info;
+    let common = &font_file.common;
+    let [pad_top, pad_right, pad_bottom, pad_left] = info.padding;
+    let padding = *info.padding.iter().max().unwrap_or(&0);
+
+    let (glyphs, glyph_indices) = glyph_table(&font_file.chars);
+    let glyph_index = |id: usize| font_file.chars.iter().position(|c| c.id == id).map(|i| glyph_indices[i] as i64);
+
+    let base = common.base as i64;
+    let descent = base - common.line_height as i64;
+    let ascent_of = |id: usize| font_file.chars.iter().find(|c| c.id == id).map(|c| base - c.yoffset as i64 - pad_top as i64);
+    let space_advance = font_file.chars.iter().find(|c| c.id == ' ' as usize).map(|c| c.xadvance as i64).unwrap_or(common.line_height as i64 / 4);
+
+    out.begin_object()?;
+
+    out.key("m_FaceInfo")?;
+    out.begin_object()?;
+    out.key("m_FamilyName")?;
+    out.string(&info.face)?;
+    out.key("m_StyleName")?;
+    out.string(match (info.bold, info.italic) {
+        (false, false) => "Regular",
+        (true, false) => "Bold",
+        (false, true) => "Italic",
+        (true, true) => "Bold Italic",
+    })?;
+    out.key("m_PointSize")?;
+    out.number(info.size as i64)?;
+    out.key("m_Scale")?;
+    out.number(1)?;
+    out.key("m_LineHeight")?;
+    out.number(common.line_height as i64)?;
+    out.key("m_AscentLine")?;
+    out.number(base)?;
+    out.key("m_CapLine")?;
+    out.number(ascent_of('H' as usize).unwrap_or(base))?;
+    out.key("m_MeanLine")?;
+    out.number(ascent_of('x' as usize).unwrap_or(base / 2))?;
+    out.key("m_Baseline")?;
+    out.number(0)?;
+    out.key("m_DescentLine")?;
+    out.number(descent)?;
+    out.key("m_SuperscriptOffset")?;
+    out.number(base)?;
+    out.key("m_SuperscriptSize")?;
+    out.float(0.5)?;
+    out.key("m_SubscriptOffset")?;
+    out.number(descent)?;
+    out.key("m_SubscriptSize")?;
+    out.float(0.5)?;
+    out.key("m_UnderlineOffset")?;
+    out.number(descent / 2)?;
+    out.key("m_UnderlineThickness")?;
+    out.number(1)?;
+    out.key("m_StrikethroughOffset")?;
+    out.number(ascent_of('x' as usize).unwrap_or(base / 2) / 2)?;
+    out.key("m_StrikethroughThickness")?;
+    out.number(1)?;
+    out.key("m_TabWidth")?;
+    out.number(space_advance)?;
+    out.end_object()?;
+
+    out.key("m_GlyphTable")?;
+    out.begin_array()?;
+    for (index, glyph) in glyphs.iter().enumerate() {
+        let width = glyph.width.saturating_sub(pad_left + pad_right) as i64;
+        let height = glyph.height.saturating_sub(pad_top + pad_bottom) as i64;
+        let x = (glyph.x + pad_left) as i64;
+        let y = common.scale_h as i64 - (glyph.y + pad_top) as i64 - height;
+
+        out.begin_object()?;
+        out.key("m_Index")?;
+        out.number(index as i64)?;
+        out.key("m_Metrics")?;
+        out.begin_object()?;
+        out.key("m_Width")?;
+        out.number(width)?;
+        out.key("m_Height")?;
+        out.number(height)?;
+        out.key("m_HorizontalBearingX")?;
+        out.number(glyph.xoffset as i64 + pad_left as i64)?;
+        out.key("m_HorizontalBearingY")?;
+        out.number(base - glyph.yoffset as i64 - pad_top as i64)?;
+        out.key("m_HorizontalAdvance")?;
+        out.number(glyph.xadvance as i64)?;
+        out.end_object()?;
+        out.key("m_GlyphRect")?;
+        out.begin_object()?;
+        out.key("m_X")?;
+        out.number(x)?;
+        out.key("m_Y")?;
+        out.number(y)?;
+        out.key("m_Width")?;
+        out.number(width)?;
+        out.key("m_Height")?;
+        out.number(height)?;
+        out.end_object()?;
+        out.key("m_Scale")?;
+        out.number(1)?;
+        out.key("m_AtlasIndex")?;
+        out.number(glyph.page as i64)?;
+        out.end_object()?;
+    }
+    out.end_array()?;
+
+    out.key("m_CharacterTable")?;
+    out.begin_array()?;
+    for (char, &index) in font_file.chars.iter().zip(&glyph_indices) {
+        out.begin_object()?;
+        out.key("m_ElementType")?;
+        out.number(1)?;
+        out.key("m_Unicode")?;
+        out.number(char.id as i64)?;
+        out.key("m_GlyphIndex")?;
+        out.number(index as i64)?;
+        out.key("m_Scale")?;
+        out.number(1)?;
+        out.end_object()?;
+    }
+    out.end_array()?;
+
+    out.key("m_FontFeatureTable")?;
+    out.begin_object()?;
+    out.key("m_GlyphPairAdjustmentRecords")?;
+    out.begin_array()?;
+    for kerning in &font_file.kernings {
+        if let (Some(first), Some(second)) = (glyph_index(kerning.first), glyph_index(kerning.second)) {
+            out.begin_object()?;
+            out.key("m_FirstAdjustmentRecord")?;
+            write_adjustment_record(&mut out, first, kerning.amount as i64)?;
+            out.key("m_SecondAdjustmentRecord")?;
+            write_adjustment_record(&mut out, second, 0)?;
+            out.key("m_FeatureLookupFlags")?;
+            out.number(0)?;
+            out.end_object()?;
+        }
+    }
+    out.end_array()?;
+    out.end_object()?;
+
+    out.key("m_AtlasWidth")?;
+    out.number(common.scale_w as i64)?;
+    out.key("m_AtlasHeight")?;
+    out.number(common.scale_h as i64)?;
+    out.key("m_AtlasPadding")?;
+    out.number(padding as i64)?;
+    out.key("m_AtlasRenderMode")?;
+    out.number(RENDER_MODE_SMOOTH_HINTED)?;
+    out.key("m_AtlasTextures")?;
+    out.begin_array()?;
+    for page in &font_file.pages {
+        out.string(&page.file)?;
+    }
+    out.end_array()?;
+
+    out.end_object()?;
+    out.newline()
+}
+
+fn write_adjustment_record(out: &mut JsonWriter, glyph_index: i64, x_advance: i64) -> io::Result<()> {
+    out.begin_object()?;
+    out.key("m_GlyphIndex")?;
+    out.number(glyph_index)?;
+    out.key("m_GlyphValueRecord")?;
+    out.begin_object()?;
+    out.key("m_XPlacement")?;
+    out.number(0)?;
+    out.key("m_YPlacement")?;
+    out.number(0)?;
+    out.key("m_XAdvance")?;
+    out.number(x_advance)?;
+    out.key("m_YAdvance")?;
+    out.number(0)?;
+    out.end_object()?;
+    out.end_object()
+}
+
+/// One char for each distinct glyph and the index of the glyph of each char. Identical bitmaps
+/// share a rect in the atlas, but are only the same glyph if they are placed alike, like comma
+/// and single low quotation mark are not. Empty glyphs all have an empty rect at 0, 0.
+fn glyph_table(chars: &[CharTag]) -> (Vec<&CharTag>, Vec<usize>) {
+    let mut glyphs: Vec<&CharTag> = Vec::new();
+    let mut indices = Vec::with_capacity(chars.len());
+    for char in chars {
+        match glyphs.iter().position(|g| same_glyph(g, char)) {
+            Some(index) => indices.push(index),
+            None => {
+                indices.push(glyphs.len());
+                glyphs.push(char);
+            },
+        }
+    }
+    (glyphs, indices)
+}
+
+fn same_glyph(a: &CharTag, b: &CharTag) -> bool {
+    a.width > 0 && a.height > 0 &&
+        a.page == b.page && a.x == b.x && a.y == b.y && a.width == b.width && a.height == b.height &&
+        a.xoffset == b.xoffset && a.yoffset == b.yoffset && a.xadvance == b.xadvance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<CharTag> {
+        let font_file = FontFile::parse(&mut format!("info face=A\ncommon\n{}", text).as_bytes()).unwrap();
+        font_file.chars
+    }
+
+    #[test]
+    fn shares_glyphs_of_chars_with_the_same_rect_and_metrics() {
+        let chars = chars("char id=65 x=0 y=0 width=8 height=9 xoffset=0 yoffset=2 xadvance=8
+char id=913 x=0 y=0 width=8 height=9 xoffset=0 yoffset=2 xadvance=8
+char id=44 x=9 y=0 width=3 height=4 xoffset=1 yoffset=9 xadvance=4
+char id=8218 x=9 y=0 width=3 height=4 xoffset=0 yoffset=9 xadvance=3
+");
+        let (glyphs, indices) = glyph_table(&chars);
+        assert_eq!(glyphs.len(), 3);
+        assert_eq!(indices, [0, 0, 1, 2]);
+        assert_eq!((glyphs[2].id, glyphs[2].xadvance), (8218, 3));
+    }
+
+    #[test]
+    fn keeps_empty_glyphs_apart() {
+        let chars = chars("char id=32 xadvance=4
+char id=160 xadvance=4
+char id=8195 xadvance=16
+");
+        let (glyphs, indices) = glyph_table(&chars);
+        assert_eq!(glyphs.len(), 3);
+        assert_eq!(indices, [0, 1, 2]);
+    }
+}
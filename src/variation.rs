@@ -0,0 +1,188 @@
+//! Variation axes and named instances of variable fonts, using FreeType's multiple masters API.
+//! For more info see the [FreeType documentation][1].
+//!
+//! [1]: https://www.freetype.org/freetype2/docs/reference/ft2-multiple_masters.html
+//!
+
+use ft;
+use ft::freetype_sys as fts;
+use std::ffi::{CStr};
+use std::ptr;
+
+#[repr(C)]
+#[allow(dead_code, non_camel_case_types)]
+struct FT_Var_Axis {
+    name: *mut fts::FT_String,
+    minimum: fts::FT_Fixed,
+    def: fts::FT_Fixed,
+    maximum: fts::FT_Fixed,
+    tag: fts::FT_ULong,
+    strid: fts::FT_UInt,
+}
+
+#[repr(C)]
+#[allow(dead_code, non_camel_case_types)]
+struct FT_Var_Named_Style {
+    coords: *mut fts::FT_Fixed,
+    strid: fts::FT_UInt,
+    psid: fts::FT_UInt,
+}
+
+#[repr(C)]
+#[allow(dead_code, non_camel_case_types)]
+struct FT_MM_Var {
+    num_axis: fts::FT_UInt,
+    num_designs: fts::FT_UInt,
+    num_namedstyles: fts::FT_UInt,
+    axis: *mut FT_Var_Axis,
+    namedstyle: *mut FT_Var_Named_Style,
+}
+
+#[repr(C)]
+#[allow(dead_code, non_camel_case_types)]
+struct FT_SfntName {
+    platform_id: fts::FT_UShort,
+    encoding_id: fts::FT_UShort,
+    language_id: fts::FT_UShort,
+    name_id: fts::FT_UShort,
+    string: *mut fts::FT_Byte,
+    string_len: fts::FT_UInt,
+}
+
+extern "C" {
+    fn FT_Get_MM_Var(face: fts::FT_Face, amaster: *mut *mut FT_MM_Var) -> fts::FT_Error;
+    fn FT_Done_MM_Var(library: fts::FT_Library, amaster: *mut FT_MM_Var) -> fts::FT_Error;
+    fn FT_Set_Var_Design_Coordinates(face: fts::FT_Face, num_coords: fts::FT_UInt, coords: *mut fts::FT_Fixed) -> fts::FT_Error;
+    fn FT_Get_Sfnt_Name_Count(face: fts::FT_Face) -> fts::FT_UInt;
+    fn FT_Get_Sfnt_Name(face: fts::FT_Face, idx: fts::FT_UInt, aname: *mut FT_SfntName) -> fts::FT_Error;
+}
+
+pub struct VariationAxis {
+    /// Four letter axis tag, e.g. `wght` or `opsz`
+    pub tag: String,
+    pub name: String,
+    pub minimum: f64,
+    pub default: f64,
+    pub maximum: f64,
+}
+
+pub struct NamedInstance {
+    pub name: String,
+    /// Design coordinates, one for each axis
+    pub coordinates: Vec<f64>,
+}
+
+pub struct Variations {
+    pub axes: Vec<VariationAxis>,
+    pub instances: Vec<NamedInstance>,
+}
+
+impl Variations {
+    /// Reads the variation axes and named instances, returns `None` if the face isn't a variable font.
+    pub fn from_face(library: &ft::Library, face: &ft::Face) -> Option<Variations> {
+        let raw_face = raw_face(face);
+        let mut mm_var: *mut FT_MM_Var = ptr::null_mut();
+
+        let err = unsafe {
+            FT_Get_MM_Var(raw_face, &mut mm_var)
+        };
+        if err != fts::FT_Err_Ok || mm_var.is_null() {
+            return None;
+        }
+
+        let variations = unsafe {
+            let mm_var = &*mm_var;
+            let axes = (0..mm_var.num_axis as isize).map(|i| {
+                let axis = &*mm_var.axis.offset(i);
+                VariationAxis {
+                    tag: tag_to_string(axis.tag),
+                    name: if axis.name.is_null() { tag_to_string(axis.tag) } else { CStr::from_ptr(axis.name).to_string_lossy().to_string() },
+                    minimum: fixed_to_f64(axis.minimum),
+                    default: fixed_to_f64(axis.def),
+                    maximum: fixed_to_f64(axis.maximum),
+                }
+            }).collect();
+            let instances = (0..mm_var.num_namedstyles as isize).map(|i| {
+                let style = &*mm_var.namedstyle.offset(i);
+                NamedInstance {
+                    name: sfnt_name(raw_face, style.strid).unwrap_or_else(|| format!("Instance {}", i + 1)),
+                    coordinates: (0..mm_var.num_axis as isize).map(|a| fixed_to_f64(*style.coords.offset(a))).collect(),
+                }
+            }).collect();
+            Variations {
+                axes: axes,
+                instances: instances,
+            }
+        };
+
+        unsafe {
+            FT_Done_MM_Var(library.raw(), mm_var);
+        }
+
+        Some(variations)
+    }
+
+    /// Name describing the coordinates, either the name of the matching named instance or a list
+    /// of axis tags and values.
+    pub fn describe(&self, coordinates: &[f64]) -> String {
+        if coordinates.is_empty() {
+            return self.describe(&self.axes.iter().map(|a| a.default).collect::<Vec<f64>>());
+        }
+
+        if let Some(instance) = self.instances.iter().find(|i| i.coordinates.as_slice() == coordinates) {
+            return instance.name.clone();
+        }
+
+        let values: Vec<String> = self.axes.iter().zip(coordinates).map(|(axis, value)| format!("{}={}", axis.tag, value)).collect();
+        values.join(",")
+    }
+}
+
+/// Selects the instance of the variable font used for rendering, empty coordinates select the
+/// default instance.
+pub fn set_coordinates(face: &ft::Face, coordinates: &[f64]) -> ft::FtResult<()> {
+    let mut fixed: Vec<fts::FT_Fixed> = coordinates.iter().map(|&c| (c * 65536.0).round() as fts::FT_Fixed).collect();
+    let err = unsafe {
+        FT_Set_Var_Design_Coordinates(raw_face(face), fixed.len() as fts::FT_UInt, fixed.as_mut_ptr())
+    };
+
+    if err == fts::FT_Err_Ok {
+        Ok(())
+    } else {
+        Err(err.into())
+    }
+}
+
+fn raw_face(face: &ft::Face) -> fts::FT_Face {
+    face.raw() as *const fts::FT_FaceRec as fts::FT_Face
+}
+
+fn fixed_to_f64(value: fts::FT_Fixed) -> f64 {
+    value as f64 / 65536.0
+}
+
+fn tag_to_string(tag: fts::FT_ULong) -> String {
+    (0..4).rev().map(|i| ((tag >> (i * 8)) & 0xff) as u8 as char).collect()
+}
+
+/// Looks up an entry of the font's name table, preferring the Unicode encoded Windows entries.
+fn sfnt_name(face: fts::FT_Face, name_id: fts::FT_UInt) -> Option<String> {
+    let mut fallback = None;
+
+    for idx in 0..unsafe { FT_Get_Sfnt_Name_Count(face) } {
+        let mut name: FT_SfntName = unsafe { ::std::mem::zeroed() };
+        if unsafe { FT_Get_Sfnt_Name(face, idx, &mut name) } != fts::FT_Err_Ok || name.name_id as fts::FT_UInt != name_id {
+            continue;
+        }
+
+        let bytes = unsafe { ::std::slice::from_raw_parts(name.string, name.string_len as usize) };
+        if name.platform_id == 0 || name.platform_id == 3 {
+            let utf16: Vec<u16> = bytes.chunks(2).filter(|c| c.len() == 2).map(|c| (c[0] as u16) << 8 | c[1] as u16).collect();
+            return Some(String::from_utf16_lossy(&utf16));
+        } else if fallback.is_none() {
+            fallback = Some(bytes.iter().map(|&b| b as char).collect());
+        }
+    }
+
+    fallback
+}